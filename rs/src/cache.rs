@@ -0,0 +1,46 @@
+use vcf::cache::save;
+use vcf::filter::Pipe;
+use vcf::matrix::GenotypeMatrix;
+use vcf::normalize::SplitAllelesFilter;
+
+use std::io::{self, Error, ErrorKind};
+
+/// Usage: `rs cache OUTPUT [INPUT]`
+///
+/// Read the genotypes of a (possibly compressed) VCF, from the given file or
+/// from the standard input, and save them as a genotype cache that `rs` can
+/// read in place of the VCF. Multi-allelic sites are split into biallelic
+/// ones first.
+pub fn main() -> io::Result<()> {
+    use std::env;
+    use std::fs::File;
+    use std::io::{stdin, BufRead, BufReader};
+    use vcf::stream::from_reader;
+
+    let args = env::args().skip(2).collect::<Vec<_>>();
+
+    let output = match args.first() {
+        Some(output) if args.len() <= 2 => output,
+        _ => {
+            let msg = "Usage: rs cache OUTPUT [INPUT]";
+            return Err(Error::new(ErrorKind::InvalidInput, msg));
+        }
+    };
+
+    let input: Box<dyn BufRead> = match args.get(1) {
+        Some(path) => Box::new(BufReader::new(File::open(path)?)),
+        None => Box::new(BufReader::new(stdin())),
+    };
+    let stream = Pipe::new(from_reader(input)?)
+        .pipe(SplitAllelesFilter::new())
+        .stream();
+
+    let matrix = GenotypeMatrix::load(stream)?;
+    eprintln!(
+        "{} variants of {} individuals",
+        matrix.variant_count(),
+        matrix.individual_count()
+    );
+
+    save(&matrix, output)
+}
@@ -1,30 +1,46 @@
-use vcf::population::Population;
+use vcf::error::VcfError;
+use vcf::population::{Population, Sex};
 use vcf::predictor::{Predictor, PredictorFactory, PredictorJoaoFactory};
 use vcf::stream::{MetadataReader, VCFData};
 
-use utils::{mean, process_variant, split, stdev};
+use utils::{alt_fractions, impute, mean, ploidies, split, stdev, Imputation};
 
 // use itertools::Itertools;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 
+/// The number of records handed to a worker thread at a time
+const BATCH_SIZE: usize = 16;
+
+#[allow(clippy::too_many_arguments)]
 fn compute_capacity<S, PF>(
     stream: S,
     population: &Population,
+    sexes: &HashMap<String, Sex>,
     repeats: usize,
     ratio: f32,
     factory: PF,
-) where
+    imputation: Imputation,
+    threads: usize,
+) -> Result<(), VcfError>
+where
     S: MetadataReader,
-    PF: PredictorFactory,
+    PF: PredictorFactory + Sync,
     <PF as PredictorFactory>::Predictor: Predictor<Prediction = f32>,
 {
-    let unfolded = stream.unfold().unwrap();
+    let unfolded = stream.unfold()?;
 
     let individuals = unfolded.individuals;
     let labels = population
         .labels(individuals.iter())
         .map(|s| s.to_string())
         .collect::<Vec<_>>();
+    let sexes = individuals
+        .iter()
+        .map(|individual| sexes.get(individual).cloned().unwrap_or(Sex::Unknown))
+        .collect::<Vec<_>>();
 
     let split_index = (individuals.len() as f32 * ratio) as usize;
 
@@ -32,37 +48,129 @@ fn compute_capacity<S, PF>(
     // every polymorphism and every repeat. We call for the number of repeats
     // and apply the corresponding one, copying the same approach for all
     // polymorphisms.
-    let train_target_indices: Vec<(HashSet<usize>, HashSet<usize>)>;
-
-    if repeats > 1 {
+    let train_target_indices: Vec<(HashSet<usize>, HashSet<usize>)> = if repeats > 1 {
         let train_target_indices_list = vec![split(0..individuals.len(), split_index); repeats];
-        train_target_indices = train_target_indices_list
+        train_target_indices_list
             .iter()
             .map(|(i, j)| (i.iter().cloned().collect(), j.iter().cloned().collect()))
-            .collect();
+            .collect()
     } else {
-        train_target_indices = vec![(
+        vec![(
             (0..individuals.len()).collect(),
             (0..individuals.len()).collect(),
-        )];
-    }
+        )]
+    };
 
     let worker = Worker {
         repeats,
-        individuals: &individuals,
         labels: &labels,
+        sexes: &sexes,
         train_target_indices: &train_target_indices,
         factory: &factory,
+        imputation,
     };
-    
-    for polymorphism in unfolded.stream {
-        let identifier = polymorphism.identifier().to_string();
-        let (m, s) = worker.process_polymorphism(polymorphism);
-        if repeats == 1 {
-            println!("{}\t{}", identifier, m);
-        } else {
-            println!("{}\t{}\t{}", identifier, m, s)
+    let worker = &worker;
+    let stream = unfolded.stream;
+
+    // This thread reads the records and sends them in numbered batches to the
+    // workers, whose results are put back in order by the printing thread.
+    // The batch queue is bounded so that reading does not run ahead of the
+    // workers and hold the whole input in memory.
+    let (batch_sender, batch_receiver) = mpsc::sync_channel::<(usize, Vec<VCFData>)>(2 * threads);
+    let batch_receiver = Arc::new(Mutex::new(batch_receiver));
+    let (result_sender, result_receiver) = mpsc::channel::<(usize, Vec<Capacity>)>();
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            // Each worker owns a handle to the queue, so that reading stops
+            // instead of blocking if they all die
+            let batch_receiver = Arc::clone(&batch_receiver);
+            let result_sender = result_sender.clone();
+            scope.spawn(move || loop {
+                // The lock is released as soon as a batch is received
+                let received = batch_receiver.lock().unwrap().recv();
+                let (index, batch) = match received {
+                    Ok(received) => received,
+                    Err(_) => break,
+                };
+                let results = batch
+                    .into_iter()
+                    .map(|polymorphism| worker.process_polymorphism(polymorphism))
+                    .collect();
+                if result_sender.send((index, results)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(batch_receiver);
+        drop(result_sender);
+
+        scope.spawn(move || {
+            let mut pending = BTreeMap::new();
+            let mut next = 0;
+            for (index, results) in result_receiver {
+                pending.insert(index, results);
+                while let Some(results) = pending.remove(&next) {
+                    for capacity in results {
+                        print_capacity(&capacity, repeats);
+                    }
+                    next += 1;
+                }
+            }
+        });
+
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        let mut index = 0;
+        for polymorphism in stream {
+            batch.push(polymorphism?);
+            if batch.len() == BATCH_SIZE {
+                let full = mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+                // Sending only fails if the other threads panicked, which is
+                // reported when the scope ends
+                if batch_sender.send((index, full)).is_err() {
+                    break;
+                }
+                index += 1;
+            }
         }
+        if !batch.is_empty() {
+            let _ = batch_sender.send((index, batch));
+        }
+
+        // Dropping the sender lets the workers, and then the printing thread,
+        // finish once every batch is processed
+        drop(batch_sender);
+
+        Ok(())
+    })
+}
+
+/// The accuracy of the predictions made from a polymorphism, missing when
+/// no target had a genotype to predict from
+struct Capacity {
+    identifier: String,
+    mean: Option<f32>,
+    stdev: Option<f32>,
+    missing: usize,
+}
+
+fn print_capacity(capacity: &Capacity, repeats: usize) {
+    let show = |value: Option<f32>| value.map_or(".".to_string(), |v| v.to_string());
+    if repeats == 1 {
+        println!(
+            "{}\t{}\t{}",
+            capacity.identifier,
+            show(capacity.mean),
+            capacity.missing
+        );
+    } else {
+        println!(
+            "{}\t{}\t{}\t{}",
+            capacity.identifier,
+            show(capacity.mean),
+            show(capacity.stdev),
+            capacity.missing
+        )
     }
 }
 
@@ -72,32 +180,30 @@ where
     <PF as PredictorFactory>::Predictor: Predictor<Prediction = f32>,
 {
     repeats: usize,
-    individuals: &'a [String],
     labels: &'a [String],
+    sexes: &'a [Sex],
     train_target_indices: &'a [(HashSet<usize>, HashSet<usize>)],
     factory: &'a PF,
+    imputation: Imputation,
 }
 
-unsafe impl<'a, PF> Send for Worker<'a, PF>
-where
-    PF: PredictorFactory,
-    <PF as PredictorFactory>::Predictor: Predictor<Prediction = f32>,
-{}
-
 impl<'a, PF> Worker<'a, PF>
 where
     PF: PredictorFactory,
     <PF as PredictorFactory>::Predictor: Predictor<Prediction = f32>,
 {
-    fn process_polymorphism(&self, polymorphism: VCFData) -> (f32, f32) {
+    /// The mean and standard deviation of the accuracy of the predictions
+    /// made from a polymorphism, along with the number of missing genotypes
+    fn process_polymorphism(&self, polymorphism: VCFData) -> Capacity {
         let mut accuracies = Vec::with_capacity(self.repeats);
 
+        let ploidies = ploidies(&polymorphism, self.sexes);
+        let genotypes = alt_fractions(&polymorphism, &ploidies);
+        let missing = genotypes.iter().filter(|g| g.is_none()).count();
+        let imputed = impute(&genotypes, self.imputation);
+
         for repeat_idx in 0..self.repeats {
-            let genotypes = polymorphism
-                .genotypes()
-                .map(|s| process_variant(s).unwrap_or(0.0))
-                .collect::<Vec<_>>();
-            let to_split = izip!(self.individuals, genotypes, self.labels);
+            let to_split = izip!(&ploidies, &genotypes, &imputed, self.labels);
 
             let mut train = Vec::new();
             let mut target = Vec::new();
@@ -113,42 +219,70 @@ where
 
             let mut genotypes_and_labels = Vec::new();
 
-            for (_individual, genotype, label) in train {
-                genotypes_and_labels.push((genotype, label.to_string()));
+            for (&ploidy, &genotype, _imputed, label) in train {
+                genotypes_and_labels.push((genotype, ploidy, label.to_string()));
             }
 
             let predictor = self.factory.build(genotypes_and_labels.iter().cloned());
             let mut count = 0.0;
-            let total = target.len() as f32;
+            let mut total = 0.0;
+
+            for (_ploidy, _genotype, &imputed, label) in target {
+                let genotype = match imputed {
+                    Some(genotype) => genotype,
+                    None => continue,
+                };
+                total += 1.0;
 
-            for (_individual, genotype, label) in target {
                 let predictions = predictor.predict(genotype);
-                if let Some(prob) = predictions.get::<str>(&label) {
+                if let Some(prob) = predictions.get::<str>(label) {
                     count += prob;
                 }
             }
 
-            accuracies.push(count / total);
+            // Without a single prediction there is no accuracy to measure
+            if total > 0.0 {
+                accuracies.push(count / total);
+            }
         }
 
-        let m = mean(&accuracies);
-        if self.repeats == 1 {
-            // println!("{}\t{:.5}", polymorphism.identifier(), m);
-            (m, 0.0)
+        let (m, s) = if accuracies.is_empty() {
+            (None, None)
+        } else if self.repeats == 1 {
+            (Some(mean(&accuracies)), Some(0.0))
         } else {
-            let s = stdev(&accuracies);
-            // println!("{}\t{:.5}\t{:.5}", polymorphism.identifier(), m, s);
-            (m, s)
+            (Some(mean(&accuracies)), Some(stdev(&accuracies)))
+        };
+
+        Capacity {
+            identifier: polymorphism.identifier().to_string(),
+            mean: m,
+            stdev: s,
+            missing,
         }
     }
 }
 
+/// Usage: `rs [--threads N] [--imputation skip|reference|mean] [--region REGION]... [INPUT]`
+///
+/// Print the capacity of each polymorphism of a (possibly compressed) VCF, of
+/// a BCF, of a genotype cache or of a PLINK fileset, given by its `.bed` file, to
+/// predict the superpopulation of individuals. The records are processed by
+/// as many threads as there are processors, unless `--threads` says
+/// otherwise. Missing genotypes are not predicted from, unless
+/// `--imputation` fills them in. Sites where no target has a genotype have
+/// their accuracy printed as `.`. With `--region`, only the records of a
+/// BGZF compressed and indexed VCF that overlap the given regions are read.
 pub fn main() -> ::std::io::Result<()> {
+    use std::env;
     use std::fs::File;
-    use std::io::{stdin, BufReader};
-    use vcf::stream::from_text_stream;
-
-    let predictor_factory = PredictorJoaoFactory { threshold: 0.0 };
+    use std::io::{stdin, BufRead, BufReader, Error, ErrorKind};
+    use vcf::bcf::{self, peek_bcf};
+    use vcf::cache::{peek_cache, read_cache};
+    use vcf::index::query;
+    use vcf::plink::Plink;
+    use vcf::population::read_sexes;
+    use vcf::stream::from_reader;
 
     let population = {
         let file = File::open("../populations/superpopulations.txt")?;
@@ -156,13 +290,112 @@ pub fn main() -> ::std::io::Result<()> {
         Population::parse(reader)?
     };
 
-    let stdin = stdin();
-    let stream = {
-        let handle = stdin.lock();
-        from_text_stream(handle)
+    let mut sexes = {
+        let file = File::open("../populations/all.csv")?;
+        read_sexes(BufReader::new(file))?
     };
 
-    compute_capacity(stream, &population, 20, 0.1, predictor_factory);
+    let usage = || {
+        let msg = "Usage: rs [--threads N] [--imputation skip|reference|mean] [--region REGION]... [INPUT]";
+        Error::new(ErrorKind::InvalidInput, msg)
+    };
+
+    let mut threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut imputation = Imputation::Skip;
+    let mut regions = Vec::new();
+    let mut path = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--threads" => {
+                threads = match args.next().and_then(|n| n.parse().ok()) {
+                    Some(n) if n > 0 => n,
+                    _ => return Err(usage()),
+                }
+            }
+            "--imputation" => {
+                imputation = match args.next().and_then(|s| s.parse().ok()) {
+                    Some(imputation) => imputation,
+                    None => return Err(usage()),
+                }
+            }
+            "--region" => match args.next() {
+                Some(region) => regions.push(region),
+                None => return Err(usage()),
+            },
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(usage()),
+        }
+    }
+
+    // PLINK filesets are given by their .bed file, and the sexes in their
+    // .fam file take precedence
+    if let Some(prefix) = path.as_ref().and_then(|path| path.strip_suffix(".bed")) {
+        let plink = Plink::new(prefix);
+        sexes.extend(plink.sexes()?);
+        run(plink, &population, &sexes, imputation, threads)?;
+        return Ok(());
+    }
+
+    // Regions are read through the index next to the file, which must be a
+    // BGZF compressed VCF
+    if !regions.is_empty() {
+        let path = path.ok_or_else(usage)?;
+        let stream = query(path, &regions)?;
+        run(stream, &population, &sexes, imputation, threads)?;
+        return Ok(());
+    }
+
+    // Read from the file given as argument, or from the standard input if
+    // there is none; either may be a (possibly compressed) VCF, a BCF or a
+    // genotype cache written by `rs cache`
+    let input: Box<dyn BufRead> = match path {
+        Some(path) => Box::new(BufReader::new(File::open(path)?)),
+        None => Box::new(BufReader::new(stdin())),
+    };
+
+    let (cache, input) = peek_cache(input)?;
+    if cache {
+        run(read_cache(input)?, &population, &sexes, imputation, threads)?;
+        return Ok(());
+    }
+
+    let (is_bcf, input) = peek_bcf(input)?;
+    if is_bcf {
+        let stream = bcf::from_reader(input)?;
+        run(stream, &population, &sexes, imputation, threads)?;
+    } else {
+        let stream = from_reader(input)?;
+        run(stream, &population, &sexes, imputation, threads)?;
+    }
 
     Ok(())
 }
+
+/// Print the capacity of the polymorphisms of a stream. Multi-allelic sites
+/// are split so that each alternate allele is assessed on its own.
+fn run<S: MetadataReader>(
+    stream: S,
+    population: &Population,
+    sexes: &HashMap<String, Sex>,
+    imputation: Imputation,
+    threads: usize,
+) -> Result<(), VcfError> {
+    use vcf::filter::Pipe;
+    use vcf::normalize::SplitAllelesFilter;
+
+    let stream = Pipe::new(stream).pipe(SplitAllelesFilter::new()).stream();
+    let predictor_factory = PredictorJoaoFactory { threshold: 0.0 };
+
+    compute_capacity(
+        stream,
+        population,
+        sexes,
+        20,
+        0.1,
+        predictor_factory,
+        imputation,
+        threads,
+    )
+}
@@ -0,0 +1,35 @@
+use vcf::concat::{Concat, MismatchPolicy};
+use vcf::writer::write_stream;
+
+use std::io::{self, Error, ErrorKind};
+
+/// Usage: `rs concat [--warn | --ignore] FILE...`
+///
+/// Write the (possibly compressed) VCF files, which must have the same
+/// individuals, one after the other as a single VCF. By default, files whose
+/// headers declare different fields are rejected; `--warn` only reports them
+/// and `--ignore` accepts them silently.
+pub fn main() -> io::Result<()> {
+    use std::env;
+
+    let mut policy = MismatchPolicy::Error;
+    let mut paths = Vec::new();
+
+    for arg in env::args().skip(2) {
+        match arg.as_str() {
+            "--warn" => policy = MismatchPolicy::Warn,
+            "--ignore" => policy = MismatchPolicy::Ignore,
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.is_empty() {
+        let msg = "Usage: rs concat [--warn | --ignore] FILE...";
+        return Err(Error::new(ErrorKind::InvalidInput, msg));
+    }
+
+    let stdout = io::stdout();
+    let handle = io::BufWriter::new(stdout.lock());
+
+    write_stream(Concat::new(paths).policy(policy), handle)
+}
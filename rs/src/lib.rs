@@ -0,0 +1,8 @@
+extern crate flate2;
+extern crate itertools;
+extern crate memchr;
+extern crate memmap2;
+extern crate rand;
+
+pub mod utils;
+pub mod vcf;
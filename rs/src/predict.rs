@@ -1,42 +1,71 @@
-use vcf::population::Population;
+use vcf::error::VcfError;
+use vcf::population::{Population, Sex};
 use vcf::predictor::{Predictor, PredictorFactory, PredictorJoaoFactory};
-use vcf::stream::MetadataReader;
+use vcf::stream::{DataStream, MetadataReader, VCFData};
 
-use utils::{process_variant, split};
+use utils::{alt_fractions, impute, ploidies, split, Imputation};
 
 use itertools::Itertools;
 use std::collections::HashMap;
-
-fn predict<S, PF>(mut stream: S, population: &Population, ratio: f32, factory: PF, min_dist: f32)
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+#[allow(clippy::too_many_arguments)]
+fn predict<S, PF>(
+    stream: S,
+    population: &Population,
+    sexes: &HashMap<String, Sex>,
+    ratio: f32,
+    factory: PF,
+    min_dist: f32,
+    imputation: Imputation,
+    mut missing_report: Option<&mut dyn Write>,
+) -> Result<(), VcfError>
 where
     S: MetadataReader,
     PF: PredictorFactory,
     <PF as PredictorFactory>::Predictor: Predictor<Prediction = f32>,
 {
-    let unfolded = stream.unfold().unwrap();
+    let unfolded = stream.unfold()?;
 
     let individuals = unfolded.individuals;
     let labels = population
         .labels(individuals.iter())
         .map(|s| s.to_string())
         .collect::<Vec<_>>();
+    let sexes = individuals
+        .iter()
+        .map(|individual| sexes.get(individual).cloned().unwrap_or(Sex::Unknown))
+        .collect::<Vec<_>>();
 
     let to_split = 0..individuals.len();
     let split_index = (to_split.len() as f32 * ratio) as usize;
-    let (train_idxs, target_idxs) = split(to_split, split_index);
+    let (_train_idxs, target_idxs) = split(to_split, split_index);
 
     let mut cumulative_labels: Vec<HashMap<String, f32>> = vec![HashMap::new(); individuals.len()];
 
-    for polymorphism in unfolded.stream {
-        let genotypes = polymorphism
-            .genotypes()
-            .map(|s| process_variant(s).unwrap_or(0.0))
-            .collect::<Vec<_>>();
-        let genotypes_and_labels = genotypes.iter().cloned().zip(labels.iter().cloned());
+    let mut stream = unfolded.stream;
+    let mut polymorphism = VCFData::default();
+    while let Some(result) = stream.read_into(&mut polymorphism) {
+        result?;
+        let ploidies = ploidies(&polymorphism, &sexes);
+        let genotypes = alt_fractions(&polymorphism, &ploidies);
+
+        if let Some(ref mut report) = missing_report {
+            let missing = genotypes.iter().filter(|g| g.is_none()).count();
+            writeln!(report, "{}\t{}", polymorphism.identifier(), missing)?;
+        }
+
+        let genotypes_and_labels =
+            izip!(genotypes.iter().cloned(), ploidies, labels.iter().cloned());
         let predictor = factory.build(genotypes_and_labels);
+        let imputed = impute(&genotypes, imputation);
 
         for &target_idx in target_idxs.iter() {
-            let genotype = genotypes[target_idx];
+            let genotype = match imputed[target_idx] {
+                Some(genotype) => genotype,
+                None => continue,
+            };
             let predictions = predictor.predict(genotype);
             for (prediction, confidence) in predictions {
                 *cumulative_labels[target_idx]
@@ -53,15 +82,22 @@ where
             .iter()
             .sorted_by(|(_, f1), (_, f2)| f2.partial_cmp(f1).unwrap());
 
-        let mut diff = (predictions[0].1 - predictions[1].1) as f32;
-        if relative {
-            diff /= predictions[0].1;
-        }
-
-        let final_prediction = if diff >= min_dist {
-            predictions[0].0
-        } else {
-            "---"
+        // Individuals may lack predictions when their genotypes are missing
+        let final_prediction = match predictions.first() {
+            Some(&(best, &best_score)) => {
+                let second_score = predictions.get(1).map_or(0.0, |p| *p.1);
+                let mut diff = best_score - second_score;
+                if relative {
+                    diff /= best_score;
+                }
+
+                if diff >= min_dist {
+                    best.as_str()
+                } else {
+                    "---"
+                }
+            }
+            None => "---",
         };
 
         println!(
@@ -69,24 +105,146 @@ where
             individuals[target_idx], labels[target_idx], final_prediction
         )
     }
+
+    Ok(())
 }
 
+/// Usage: `rs predict [--imputation skip|reference|mean] [--missing FILE] [--region REGION]... [INPUT]`
+///
+/// Print the superpopulation predicted for a random tenth of the individuals
+/// of a (possibly compressed) VCF, of a BCF, of a genotype cache or of a PLINK
+/// fileset, given by its `.bed` file, from the rest of them. Missing genotypes
+/// are not predicted from, unless `--imputation` fills them in. With
+/// `--missing`, the number of missing genotypes of each polymorphism is
+/// written to the given file. With `--region`, only the records of a BGZF
+/// compressed and indexed VCF that overlap the given regions are read.
 pub fn main() -> ::std::io::Result<()> {
-    use std::fs::File;
-    use std::io::{stdin, BufReader};
-    use vcf::stream::from_text_stream;
-
-    let predictor_factory = PredictorJoaoFactory { threshold: 0.0 };
+    use std::env;
+    use std::io::{stdin, BufRead, BufReader, Error, ErrorKind};
+    use vcf::bcf::{self, peek_bcf};
+    use vcf::cache::{peek_cache, read_cache};
+    use vcf::index::query;
+    use vcf::plink::Plink;
+    use vcf::population::read_sexes;
+    use vcf::stream::from_reader;
 
     let file = File::open("../populations/superpopulations.txt")?;
     let reader = BufReader::new(file);
     let population = Population::parse(reader)?;
 
-    let stdin = stdin();
-    let handle = stdin.lock();
-    let stream = from_text_stream(handle);
+    let file = File::open("../populations/all.csv")?;
+    let mut sexes = read_sexes(BufReader::new(file))?;
+
+    let usage = || {
+        let msg = "Usage: rs predict [--imputation skip|reference|mean] [--missing FILE] [--region REGION]... [INPUT]";
+        Error::new(ErrorKind::InvalidInput, msg)
+    };
+
+    let mut imputation = Imputation::Skip;
+    let mut missing_report = None;
+    let mut regions = Vec::new();
+    let mut path = None;
+
+    let mut args = env::args().skip(2);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--imputation" => {
+                imputation = match args.next().and_then(|s| s.parse().ok()) {
+                    Some(imputation) => imputation,
+                    None => return Err(usage()),
+                }
+            }
+            "--missing" => match args.next() {
+                Some(path) => missing_report = Some(BufWriter::new(File::create(path)?)),
+                None => return Err(usage()),
+            },
+            "--region" => match args.next() {
+                Some(region) => regions.push(region),
+                None => return Err(usage()),
+            },
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(usage()),
+        }
+    }
 
-    predict(stream, &population, 0.1, predictor_factory, 0.0);
+    let options = Options {
+        imputation,
+        missing_report,
+    };
+
+    // PLINK filesets are given by their .bed file, and the sexes in their
+    // .fam file take precedence
+    if let Some(prefix) = path.as_ref().and_then(|path| path.strip_suffix(".bed")) {
+        let plink = Plink::new(prefix);
+        sexes.extend(plink.sexes()?);
+        run(plink, &population, &sexes, options)?;
+        return Ok(());
+    }
+
+    // Regions are read through the index next to the file, which must be a
+    // BGZF compressed VCF
+    if !regions.is_empty() {
+        let path = path.ok_or_else(usage)?;
+        run(query(path, &regions)?, &population, &sexes, options)?;
+        return Ok(());
+    }
+
+    // Otherwise the input may be a (possibly compressed) VCF, a BCF or a
+    // genotype cache
+    let input: Box<dyn BufRead> = match path {
+        Some(path) => Box::new(BufReader::new(File::open(path)?)),
+        None => Box::new(BufReader::new(stdin())),
+    };
+
+    let (cache, input) = peek_cache(input)?;
+    if cache {
+        run(read_cache(input)?, &population, &sexes, options)?;
+        return Ok(());
+    }
+
+    let (is_bcf, input) = peek_bcf(input)?;
+    if is_bcf {
+        run(bcf::from_reader(input)?, &population, &sexes, options)?;
+    } else {
+        run(from_reader(input)?, &population, &sexes, options)?;
+    }
 
     Ok(())
 }
+
+/// What to do with missing genotypes, and where to report them
+struct Options {
+    imputation: Imputation,
+    missing_report: Option<BufWriter<File>>,
+}
+
+/// Print the predictions made from the polymorphisms of a stream, with
+/// multi-allelic sites split into biallelic ones
+fn run<S: MetadataReader>(
+    stream: S,
+    population: &Population,
+    sexes: &HashMap<String, Sex>,
+    mut options: Options,
+) -> Result<(), VcfError> {
+    use vcf::filter::Pipe;
+    use vcf::normalize::SplitAllelesFilter;
+
+    let stream = Pipe::new(stream).pipe(SplitAllelesFilter::new()).stream();
+    let predictor_factory = PredictorJoaoFactory { threshold: 0.0 };
+
+    predict(
+        stream,
+        population,
+        sexes,
+        0.1,
+        predictor_factory,
+        0.0,
+        options.imputation,
+        options.missing_report.as_mut().map(|w| w as &mut dyn Write),
+    )?;
+
+    match options.missing_report {
+        Some(mut report) => Ok(report.flush()?),
+        None => Ok(()),
+    }
+}
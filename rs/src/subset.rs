@@ -0,0 +1,79 @@
+use vcf::filter::{IndividualsFilter, Pipe, PolymorphismFilter};
+use vcf::stream::MetadataReader;
+use vcf::writer::write_stream;
+
+use std::io::{self, BufRead, Error, ErrorKind};
+
+/// Read a list of identifiers, one per line, ignoring comments and empty lines
+fn read_identifiers<R: BufRead>(reader: R) -> io::Result<Vec<String>> {
+    let mut identifiers = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+
+        // Remove possible comments and surrounding spaces
+        let line = match line.find('#') {
+            Some(comment_start) => &line[..comment_start],
+            None => &line,
+        };
+        let line = line.trim();
+
+        if !line.is_empty() {
+            identifiers.push(line.to_string());
+        }
+    }
+
+    Ok(identifiers)
+}
+
+/// Write the part of the stream that pertains to the given polymorphisms and,
+/// optionally, individuals
+fn subset<S: MetadataReader>(
+    stream: S,
+    polymorphisms: Vec<String>,
+    individuals: Option<Vec<String>>,
+) -> io::Result<()> {
+    let stdout = io::stdout();
+    let handle = io::BufWriter::new(stdout.lock());
+
+    let stream = Pipe::new(stream).pipe(PolymorphismFilter::new(polymorphisms));
+
+    match individuals {
+        Some(individuals) => {
+            let stream = stream.pipe(IndividualsFilter::new(individuals));
+            write_stream(stream.stream(), handle)
+        }
+        None => write_stream(stream.stream(), handle),
+    }
+}
+
+/// Usage: `rs subset POLYMORPHISMS [INDIVIDUALS] < input.vcf`
+///
+/// Extract the polymorphisms listed in the given file from a (possibly
+/// compressed) VCF in the standard input, keeping only the individuals listed
+/// in the second file if one is given, and write the result as VCF.
+pub fn main() -> io::Result<()> {
+    use std::env;
+    use std::fs::File;
+    use std::io::{stdin, BufReader};
+    use vcf::stream::from_reader;
+
+    let args = env::args().skip(2).collect::<Vec<_>>();
+
+    let polymorphisms = match args.first() {
+        Some(path) => read_identifiers(BufReader::new(File::open(path)?))?,
+        None => {
+            let msg = "Usage: rs subset POLYMORPHISMS [INDIVIDUALS] < input.vcf";
+            return Err(Error::new(ErrorKind::InvalidInput, msg));
+        }
+    };
+
+    let individuals = match args.get(1) {
+        Some(path) => Some(read_identifiers(BufReader::new(File::open(path)?))?),
+        None => None,
+    };
+
+    let stream = from_reader(BufReader::new(stdin()))?;
+
+    subset(stream, polymorphisms, individuals)
+}
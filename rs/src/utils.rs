@@ -1,12 +1,15 @@
-use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::hash::Hash;
-use std::sync::Mutex;
+use std::str::FromStr;
 
 use rand::{thread_rng, Rng};
 
+use vcf::genotype::expected_ploidy;
+use vcf::population::Sex;
+use vcf::stream::VCFData;
+
 /// Count the number of occurrences of each value in an iterator
-pub fn histogram<K, I>(mut iter: I) -> HashMap<K, u32>
+pub fn histogram<K, I>(iter: I) -> HashMap<K, u32>
 where
     K: Ord + Hash,
     I: Iterator<Item = K>,
@@ -20,17 +23,25 @@ where
     counter
 }
 
+/// The alternate allele frequency of each label, from the fraction of
+/// alternate alleles and the ploidy of each sample, so that haploid samples
+/// count for one allele and diploid ones for two. Missing genotypes are left
+/// out, so labels whose genotypes are all missing are absent from the result.
 pub fn compute_frequencies<L, I>(genotypes_and_labels: I) -> HashMap<L, f32>
 where
     L: Eq + Hash + Clone,
-    I: Iterator<Item = (f32, L)>,
+    I: Iterator<Item = (Option<f32>, usize, L)>,
 {
     let mut counts: HashMap<L, f32> = HashMap::new();
     let mut totals: HashMap<L, u32> = HashMap::new();
 
-    for (genotype, label) in genotypes_and_labels {
-        *counts.entry(label.clone()).or_default() += genotype;
-        *totals.entry(label.clone()).or_default() += 1u32;
+    for (genotype, ploidy, label) in genotypes_and_labels {
+        let genotype = match genotype {
+            Some(genotype) if ploidy > 0 => genotype,
+            _ => continue,
+        };
+        *counts.entry(label.clone()).or_default() += genotype * ploidy as f32;
+        *totals.entry(label.clone()).or_default() += ploidy as u32;
     }
 
     counts
@@ -39,30 +50,108 @@ where
         .collect()
 }
 
-lazy_static! {
-    static ref PROCESS_VARIANT_CACHE: Mutex<HashMap<String, Option<f32>>> =
-        Mutex::new(HashMap::new());
+/// The number of alleles each sample carries at a polymorphism, given the sex
+/// of each sample. Where the sex does not tell, such as on chrX for samples of
+/// unknown sex, the ploidy of the GT call is used, or 2 if there is none.
+pub fn ploidies(polymorphism: &VCFData, sexes: &[Sex]) -> Vec<usize> {
+    let contig = polymorphism.chromosome();
+    let position = polymorphism.position().parse().unwrap_or(0);
+
+    if let Some(calls) = polymorphism.decoded_calls() {
+        return sexes
+            .iter()
+            .enumerate()
+            .map(|(idx, &sex)| {
+                expected_ploidy(contig, position, sex).unwrap_or_else(|| calls.ploidy(idx))
+            })
+            .collect();
+    }
+
+    polymorphism
+        .calls()
+        .zip(sexes)
+        .map(|(call, &sex)| {
+            expected_ploidy(contig, position, sex)
+                .or_else(|| call.map(|call| call.ploidy()))
+                .unwrap_or(2)
+        })
+        .collect()
 }
 
-pub fn process_variant(variant: &str) -> Option<f32> {
-    let mut lock = PROCESS_VARIANT_CACHE.lock().unwrap();
+/// The fraction of alternate alleles of each sample, taken from the hard calls
+/// in GT where available, or else from the dosage (DS) or genotype
+/// probabilities (GP) that imputed data carries instead. Dosages are scaled
+/// by the ploidy of each sample; samples with no copy of the position, such as
+/// females on chrY, have no genotype.
+pub fn alt_fractions(polymorphism: &VCFData, ploidies: &[usize]) -> Vec<Option<f32>> {
+    ploidies
+        .iter()
+        .enumerate()
+        .map(|(idx, &ploidy)| {
+            if ploidy == 0 {
+                return None;
+            }
+
+            polymorphism
+                .call(idx)
+                .and_then(|g| g.alt_fraction())
+                .or_else(|| Some(polymorphism.dosage(idx)? / ploidy as f32))
+                .or_else(|| {
+                    // The expected number of alternate alleles, from the
+                    // probabilities of having 0, 1, 2, ... of them
+                    let probabilities = polymorphism.genotype_probabilities(idx)?;
+                    let expected = probabilities
+                        .iter()
+                        .enumerate()
+                        .map(|(count, p)| count as f32 * p)
+                        .sum::<f32>();
+                    Some(expected / (probabilities.len() as f32 - 1.0).max(1.0))
+                })
+        })
+        .collect()
+}
 
-    let entry = match lock.entry(variant.to_string()) {
-        Entry::Occupied(entry) => return *entry.get(),
-        Entry::Vacant(entry) => entry,
-    };
+/// What to do with samples whose genotype is missing when making predictions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Imputation {
+    /// Make no prediction for them
+    Skip,
+    /// Assume they are homozygous for the reference allele
+    Reference,
+    /// Use the mean of the genotypes that are not missing
+    Mean,
+}
+
+impl FromStr for Imputation {
+    type Err = &'static str;
 
-    let genotype = variant.split(':').nth(0)?;
+    fn from_str(s: &str) -> Result<Imputation, &'static str> {
+        match s {
+            "skip" => Ok(Imputation::Skip),
+            "reference" => Ok(Imputation::Reference),
+            "mean" => Ok(Imputation::Mean),
+            _ => Err("Imputation is not one of skip, reference or mean"),
+        }
+    }
+}
 
-    let alleles = if genotype.find('|').is_some() {
-        genotype.split('|')
-    } else {
-        genotype.split('/')
-    }.collect::<Vec<_>>();
+/// Fill in the missing genotypes according to `imputation`; with
+/// `Imputation::Skip` they stay missing
+pub fn impute(genotypes: &[Option<f32>], imputation: Imputation) -> Vec<Option<f32>> {
+    let fill = match imputation {
+        Imputation::Skip => None,
+        Imputation::Reference => Some(0.0),
+        Imputation::Mean => {
+            let known = genotypes.iter().filter_map(|&g| g).collect::<Vec<_>>();
+            if known.is_empty() {
+                None
+            } else {
+                Some(mean(&known))
+            }
+        }
+    };
 
-    let result = Some(alleles.iter().filter(|i| **i != "0").count() as f32 / alleles.len() as f32);
-    entry.insert(result);
-    result
+    genotypes.iter().map(|g| g.or(fill)).collect()
 }
 
 pub fn split<I, T>(iter: I, split_index: usize) -> (Vec<T>, Vec<T>)
@@ -74,8 +163,8 @@ where
     let slice = &mut vec;
     thread_rng().shuffle(slice);
     (
-        slice[split_index..].iter().cloned().collect(),
-        slice[..split_index].iter().cloned().collect(),
+        slice[split_index..].to_vec(),
+        slice[..split_index].to_vec(),
     )
 }
 
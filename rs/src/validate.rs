@@ -0,0 +1,37 @@
+use vcf::validate::validate;
+
+use std::io::{self, BufRead};
+
+/// Usage: `rs validate [INPUT]`
+///
+/// Check a (possibly compressed) VCF, read from the given file or the standard
+/// input, and write a tab-separated report of every violation found. Exits
+/// with a failure status if there is any.
+pub fn main() -> io::Result<()> {
+    use std::env;
+    use std::fs::File;
+    use std::io::{stdin, BufReader};
+    use std::process;
+
+    let input: Box<dyn BufRead> = match env::args().nth(2) {
+        Some(path) => Box::new(BufReader::new(File::open(path)?)),
+        None => Box::new(BufReader::new(stdin())),
+    };
+
+    let report = validate(input)?;
+
+    let stdout = io::stdout();
+    report.write_tsv(io::BufWriter::new(stdout.lock()))?;
+
+    eprintln!(
+        "{} violations in {} records",
+        report.violations.len(),
+        report.records
+    );
+
+    if !report.is_valid() {
+        process::exit(1);
+    }
+
+    Ok(())
+}
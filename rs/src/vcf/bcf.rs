@@ -0,0 +1,878 @@
+use std::fmt::Write;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+use std::str;
+
+use vcf::error::VcfError;
+use vcf::genotype::GenotypeArray;
+use vcf::header::parse_structured;
+use vcf::stream::{
+    decompress, peek, DataStream, IndividualsReader, IndividualsReaderFromTextStream,
+    MetadataReader, MetadataReaderFromTextStream, Peeked, VCFData,
+};
+
+/// The magic bytes at the start of a BCF2 file; the minor version follows
+const BCF_MAGIC: &[u8] = b"BCF\x02";
+
+/// The type codes of typed values
+const TYPE_MISSING: u8 = 0;
+const TYPE_INT8: u8 = 1;
+const TYPE_INT16: u8 = 2;
+const TYPE_INT32: u8 = 3;
+const TYPE_FLOAT: u8 = 5;
+const TYPE_CHAR: u8 = 7;
+
+const FLOAT_MISSING: u32 = 0x7F80_0001;
+const FLOAT_END_OF_VECTOR: u32 = 0x7F80_0002;
+
+/// Create a stream from a reader over a BCF2 file, which is usually BGZF
+/// compressed. Records are converted to `VCFData` so that the rest of the
+/// pipeline does not need to know where they came from.
+pub fn from_reader<R: BufRead + 'static>(reader: R) -> io::Result<impl MetadataReader> {
+    Ok(MetadataReaderFromBcf {
+        reader: decompress(reader)?,
+    })
+}
+
+/// Read enough of the start of a reader, once decompressed, to tell whether
+/// it is a BCF2 file, and give back a reader over its decompressed contents
+pub fn peek_bcf<R: BufRead + 'static>(reader: R) -> io::Result<(bool, Peeked<Box<dyn BufRead>>)> {
+    let reader = peek(decompress(reader)?, BCF_MAGIC.len())?;
+    Ok((reader.start() == BCF_MAGIC, reader))
+}
+
+/// Create a stream from the BCF2 file at the given path
+pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<impl MetadataReader> {
+    let file = File::open(path)?;
+    from_reader(BufReader::new(file))
+}
+
+/// The dictionaries that BCF records use to refer to header definitions
+struct Dictionary {
+    strings: Vec<String>,
+    contigs: Vec<String>,
+}
+
+impl Dictionary {
+    /// Build the dictionaries from the metadata lines, in the order in which
+    /// they are defined, honouring explicit `IDX` fields
+    fn new(metadata: &[(String, String)]) -> Dictionary {
+        let mut strings = vec!["PASS".to_string()];
+        let mut contigs = Vec::new();
+
+        for (key, value) in metadata {
+            let dictionary = match key.as_str() {
+                "FILTER" | "INFO" | "FORMAT" => &mut strings,
+                "contig" => &mut contigs,
+                _ => continue,
+            };
+
+            let attributes = parse_structured(value).unwrap_or_default();
+            let field = |key| attributes.iter().find(|(k, _)| k == key).map(|(_, v)| &**v);
+
+            let id = match field("ID") {
+                Some(id) => id,
+                None => continue,
+            };
+
+            let idx = field("IDX").and_then(|idx| idx.parse().ok());
+            match idx {
+                Some(idx) => {
+                    if dictionary.len() <= idx {
+                        dictionary.resize(idx + 1, String::new());
+                    }
+                    dictionary[idx] = id.to_string();
+                }
+                None => {
+                    if !dictionary.iter().any(|s| s == id) {
+                        dictionary.push(id.to_string());
+                    }
+                }
+            }
+        }
+
+        Dictionary { strings, contigs }
+    }
+
+    fn string(&self, idx: i32) -> Result<&str, &'static str> {
+        self.strings
+            .get(idx as usize)
+            .map(|s| &**s)
+            .ok_or("BCF record refers to an undefined header entry")
+    }
+
+    fn contig(&self, idx: i32) -> Result<&str, &'static str> {
+        self.contigs
+            .get(idx as usize)
+            .map(|s| &**s)
+            .ok_or("BCF record refers to an undefined contig")
+    }
+}
+
+struct MetadataReaderFromBcf<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> MetadataReader for MetadataReaderFromBcf<R> {
+    type Next = IndividualsReaderFromBcf<R>;
+
+    fn read_metadata(
+        mut self,
+        map: &mut Vec<(String, String)>,
+    ) -> Result<Self::Next, VcfError> {
+        let mut magic = [0u8; 5];
+        self.reader
+            .read_exact(&mut magic)
+            .map_err(|_| "BCF file is empty")?;
+        if !magic.starts_with(BCF_MAGIC) {
+            return Err("Not a BCF2 file".into());
+        }
+
+        let mut text = vec![0u8; read_u32(&mut self.reader)? as usize];
+        self.reader
+            .read_exact(&mut text)
+            .map_err(|_| "Error reading the BCF header")?;
+        while text.last() == Some(&0) {
+            text.pop();
+        }
+
+        // The header is plain VCF text, so parse it as such
+        let start_len = map.len();
+        let header = MetadataReaderFromTextStream::new(Cursor::new(text)).read_metadata(map)?;
+        let dictionary = Dictionary::new(&map[start_len..]);
+
+        Ok(IndividualsReaderFromBcf {
+            reader: self.reader,
+            header,
+            dictionary,
+        })
+    }
+}
+
+struct IndividualsReaderFromBcf<R: Read> {
+    reader: R,
+    header: IndividualsReaderFromTextStream<Cursor<Vec<u8>>>,
+    dictionary: Dictionary,
+}
+
+impl<R: Read> IndividualsReader for IndividualsReaderFromBcf<R> {
+    type Next = DataStreamFromBcf<R>;
+
+    fn read_individuals(self, list: &mut Vec<String>) -> Result<Self::Next, VcfError> {
+        let start_len = list.len();
+        let (_, genotype, _) = self.header.read_individuals(list)?.into_inner();
+
+        Ok(DataStreamFromBcf {
+            reader: self.reader,
+            dictionary: self.dictionary,
+            samples: list.len() - start_len,
+            genotype,
+            shared: Vec::new(),
+            indiv: Vec::new(),
+            record: 0,
+            failed: false,
+        })
+    }
+}
+
+struct DataStreamFromBcf<R: Read> {
+    reader: R,
+    dictionary: Dictionary,
+    samples: usize,
+    genotype: bool,
+    shared: Vec<u8>,
+    indiv: Vec<u8>,
+
+    // The number of records read so far, reported in place of line numbers
+    record: usize,
+    failed: bool,
+}
+
+impl<R: Read> DataStreamFromBcf<R> {
+    /// Read the next record into the internal buffers, returning `false` at
+    /// the end of the file
+    fn read_record(&mut self) -> Result<bool, VcfError> {
+        let mut lengths = [0u8; 8];
+        let mut read = 0;
+        while read < lengths.len() {
+            match self.reader.read(&mut lengths[read..]) {
+                Ok(0) if read == 0 => return Ok(false),
+                Ok(0) => return Err(self.error("BCF record is truncated")),
+                Ok(len) => read += len,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(VcfError::io(Some(self.record + 1), e)),
+            }
+        }
+        self.record += 1;
+
+        let l_shared = u32::from_le_bytes([lengths[0], lengths[1], lengths[2], lengths[3]]);
+        let l_indiv = u32::from_le_bytes([lengths[4], lengths[5], lengths[6], lengths[7]]);
+
+        self.shared.resize(l_shared as usize, 0);
+        self.indiv.resize(l_indiv as usize, 0);
+
+        let result = self
+            .reader
+            .read_exact(&mut self.shared)
+            .and_then(|_| self.reader.read_exact(&mut self.indiv));
+        match result {
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                Err(self.error("BCF record is truncated"))
+            }
+            Err(e) => Err(VcfError::io(Some(self.record), e)),
+            Ok(()) => Ok(true),
+        }
+    }
+
+    /// An error in the current record
+    fn error(&self, message: &str) -> VcfError {
+        VcfError::syntax(Some(self.record), None, "", message)
+    }
+
+    /// Convert the record in the internal buffers into a `VCFData`
+    fn decode(&self) -> Result<VCFData, &'static str> {
+        let mut record = RecordBuilder::new();
+        let mut shared = Buffer::new(&self.shared);
+
+        let chrom = shared.i32()?;
+        let pos = shared.i32()?;
+        let _rlen = shared.i32()?;
+        let qual = shared.u32()?;
+        let n_allele_info = shared.u32()?;
+        let n_fmt_sample = shared.u32()?;
+
+        let n_allele = (n_allele_info >> 16) as usize;
+        let n_info = (n_allele_info & 0xffff) as usize;
+        let n_fmt = (n_fmt_sample >> 24) as usize;
+        let n_sample = (n_fmt_sample & 0xff_ffff) as usize;
+
+        if n_sample != self.samples && n_fmt > 0 {
+            return Err("BCF record has the wrong number of samples");
+        }
+
+        // CHROM and POS
+        record.line.push_str(self.dictionary.contig(chrom)?);
+        record.next_field();
+        push_int(&mut record.line, i64::from(pos) + 1);
+        record.next_field();
+
+        // ID
+        let (kind, len) = shared.descriptor()?;
+        shared.write_values(&mut record.line, kind, len)?;
+        record.next_field();
+
+        // REF and ALT
+        for idx in 0..n_allele {
+            if idx > 1 {
+                record.line.push(',');
+            }
+            let (kind, len) = shared.descriptor()?;
+            shared.write_values(&mut record.line, kind, len)?;
+            if idx == 0 {
+                record.next_field();
+            }
+        }
+        if n_allele < 2 {
+            record.line.push('.');
+            if n_allele == 0 {
+                record.next_field();
+                record.line.push('.');
+            }
+        }
+        record.next_field();
+
+        // QUAL
+        if qual == FLOAT_MISSING {
+            record.line.push('.');
+        } else {
+            write!(record.line, "{}", f32::from_bits(qual)).unwrap();
+        }
+        record.next_field();
+
+        // FILTER
+        let (kind, len) = shared.descriptor()?;
+        if len == 0 {
+            record.line.push('.');
+        }
+        for idx in 0..len {
+            if idx > 0 {
+                record.line.push(';');
+            }
+            if let Int::Value(filter) = shared.int(kind)? {
+                record.line.push_str(self.dictionary.string(filter)?);
+            }
+        }
+        record.next_field();
+
+        // INFO
+        if n_info == 0 {
+            record.line.push('.');
+        }
+        for idx in 0..n_info {
+            if idx > 0 {
+                record.line.push(';');
+            }
+            let key = shared.typed_int()?;
+            record.line.push_str(self.dictionary.string(key)?);
+
+            let (kind, len) = shared.descriptor()?;
+            if kind != TYPE_MISSING && len > 0 {
+                record.line.push('=');
+                shared.write_values(&mut record.line, kind, len)?;
+            }
+        }
+
+        let mut calls = None;
+        if self.genotype {
+            record.next_field();
+            calls = self.decode_samples(&mut record, n_fmt)?;
+        } else {
+            // Keep the indices of the fields aligned with files that do have
+            // genotype columns, as the text reader does
+            record.empty_field();
+        }
+
+        let data = record.finish();
+        Ok(match calls {
+            Some(calls) => data.with_calls(calls),
+            None => data,
+        })
+    }
+
+    /// Write the FORMAT column and one column per sample. The values in the
+    /// record are grouped by FORMAT key rather than by sample, so first find
+    /// where each key starts and then interleave them. The GT values are also
+    /// returned as typed calls, so that they need not be parsed from the text.
+    fn decode_samples(
+        &self,
+        record: &mut RecordBuilder,
+        n_fmt: usize,
+    ) -> Result<Option<GenotypeArray>, &'static str> {
+        let mut indiv = Buffer::new(&self.indiv);
+        let mut fields = Vec::with_capacity(n_fmt);
+
+        for idx in 0..n_fmt {
+            let key = self.dictionary.string(indiv.typed_int()?)?;
+            let (kind, len) = indiv.descriptor()?;
+            let start = indiv.pos;
+            indiv.skip(self.samples * len * type_size(kind)?)?;
+
+            if idx > 0 {
+                record.line.push(':');
+            }
+            record.line.push_str(key);
+
+            fields.push((key == "GT", kind, len, start));
+        }
+
+        if n_fmt == 0 {
+            record.line.push('.');
+        }
+
+        let ploidy = fields.iter().find(|&&(gt, ..)| gt).map(|&(_, _, len, _)| len);
+        let mut calls = ploidy.map(|ploidy| Vec::with_capacity(self.samples * ploidy));
+
+        for sample in 0..self.samples {
+            record.next_field();
+
+            if n_fmt == 0 {
+                record.line.push('.');
+            }
+
+            for (idx, &(gt, kind, len, start)) in fields.iter().enumerate() {
+                if idx > 0 {
+                    record.line.push(':');
+                }
+
+                let mut values = Buffer::new(&self.indiv);
+                values.pos = start + sample * len * type_size(kind)?;
+
+                match calls {
+                    Some(ref mut calls) if gt => {
+                        values.write_genotype(&mut record.line, kind, len, calls)?;
+                    }
+                    _ => values.write_values(&mut record.line, kind, len)?,
+                }
+            }
+        }
+
+        Ok(ploidy.and_then(|ploidy| Some(GenotypeArray::new(ploidy, calls?))))
+    }
+}
+
+impl<R: Read> Iterator for DataStreamFromBcf<R> {
+    type Item = Result<VCFData, VcfError>;
+
+    fn next(&mut self) -> Option<Result<VCFData, VcfError>> {
+        if self.failed {
+            return None;
+        }
+
+        let result = match self.read_record() {
+            Ok(true) => self.decode().map_err(|msg| self.error(msg)),
+            Ok(false) => return None,
+            Err(e) => Err(e),
+        };
+
+        self.failed = result.is_err();
+        Some(result)
+    }
+}
+
+impl<R: Read> DataStream for DataStreamFromBcf<R> {}
+
+/// Builds the text of a data line along with the ranges of its fields
+struct RecordBuilder {
+    line: String,
+    ranges: Vec<(usize, usize)>,
+    start: usize,
+}
+
+impl RecordBuilder {
+    fn new() -> RecordBuilder {
+        RecordBuilder {
+            line: String::new(),
+            ranges: Vec::new(),
+            start: 0,
+        }
+    }
+
+    /// End the current field and start a new one
+    fn next_field(&mut self) {
+        self.ranges.push((self.start, self.line.len()));
+        self.line.push('\t');
+        self.start = self.line.len();
+    }
+
+    /// Add an empty field right after the current one without changing the
+    /// text of the line
+    fn empty_field(&mut self) {
+        let end = self.line.len();
+        self.ranges.push((self.start, end));
+        self.start = end;
+    }
+
+    fn finish(mut self) -> VCFData {
+        self.ranges.push((self.start, self.line.len()));
+        VCFData::new(self.line, self.ranges)
+    }
+}
+
+/// A single integer from a typed vector
+enum Int {
+    Value(i32),
+    Missing,
+    EndOfVector,
+}
+
+/// A cursor over the bytes of a record
+struct Buffer<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Buffer<'a> {
+    fn new(bytes: &'a [u8]) -> Buffer<'a> {
+        Buffer { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        if self.pos + len > self.bytes.len() {
+            return Err("BCF record is truncated");
+        }
+        let slice = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), &'static str> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u32(&mut self) -> Result<u32, &'static str> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn i32(&mut self) -> Result<i32, &'static str> {
+        self.u32().map(|v| v as i32)
+    }
+
+    /// Read the descriptor of a typed value, returning its type and the
+    /// number of elements
+    fn descriptor(&mut self) -> Result<(u8, usize), &'static str> {
+        let byte = self.take(1)?[0];
+        let kind = byte & 0x0f;
+        let len = match byte >> 4 {
+            15 => match self.typed_int()? {
+                len if len >= 0 => len as usize,
+                _ => return Err("BCF record has a negative vector length"),
+            },
+            len => len as usize,
+        };
+        Ok((kind, len))
+    }
+
+    /// Read a typed value holding a single integer
+    fn typed_int(&mut self) -> Result<i32, &'static str> {
+        let (kind, len) = self.descriptor()?;
+        match (len, self.int(kind)?) {
+            (1, Int::Value(value)) => Ok(value),
+            _ => Err("BCF record has an invalid integer"),
+        }
+    }
+
+    fn int(&mut self, kind: u8) -> Result<Int, &'static str> {
+        let (value, missing, end) = match kind {
+            TYPE_INT8 => {
+                let v = self.take(1)?[0] as i8;
+                (i32::from(v), i32::from(i8::MIN), i32::from(i8::MIN) + 1)
+            }
+            TYPE_INT16 => {
+                let b = self.take(2)?;
+                let v = i16::from_le_bytes([b[0], b[1]]);
+                (i32::from(v), i32::from(i16::MIN), i32::from(i16::MIN) + 1)
+            }
+            TYPE_INT32 => (self.i32()?, i32::MIN, i32::MIN + 1),
+            _ => return Err("BCF record has an invalid integer type"),
+        };
+
+        Ok(if value == missing {
+            Int::Missing
+        } else if value == end {
+            Int::EndOfVector
+        } else {
+            Int::Value(value)
+        })
+    }
+
+    /// Write a typed vector of `len` elements as VCF text
+    fn write_values(
+        &mut self,
+        line: &mut String,
+        kind: u8,
+        len: usize,
+    ) -> Result<(), &'static str> {
+        let start = line.len();
+        let mut ended = false;
+
+        match kind {
+            TYPE_MISSING => {}
+            TYPE_CHAR => {
+                let bytes = self.take(len)?;
+                let end = bytes.iter().position(|&b| b == 0).unwrap_or(len);
+                let text = str::from_utf8(&bytes[..end]).map_err(|_| "BCF string is not UTF-8")?;
+                line.push_str(text);
+            }
+            TYPE_FLOAT => {
+                for idx in 0..len {
+                    let bits = self.u32()?;
+                    if ended || bits == FLOAT_END_OF_VECTOR {
+                        ended = true;
+                        continue;
+                    }
+                    if idx > 0 {
+                        line.push(',');
+                    }
+                    if bits == FLOAT_MISSING {
+                        line.push('.');
+                    } else {
+                        write!(line, "{}", f32::from_bits(bits)).unwrap();
+                    }
+                }
+            }
+            _ => {
+                for idx in 0..len {
+                    let value = self.int(kind)?;
+                    if ended {
+                        continue;
+                    }
+                    if let Int::EndOfVector = value {
+                        ended = true;
+                        continue;
+                    }
+                    if idx > 0 {
+                        line.push(',');
+                    }
+                    match value {
+                        Int::Value(value) => push_int(line, i64::from(value)),
+                        _ => line.push('.'),
+                    }
+                }
+            }
+        }
+
+        if line.len() == start {
+            line.push('.');
+        }
+
+        Ok(())
+    }
+
+    /// Write a typed GT vector, where each allele is encoded as
+    /// `(index + 1) << 1 | phased`, and 0 is a missing allele. The values are
+    /// also added to `calls`, as a `GenotypeArray` holds them.
+    fn write_genotype(
+        &mut self,
+        line: &mut String,
+        kind: u8,
+        len: usize,
+        calls: &mut Vec<i32>,
+    ) -> Result<(), &'static str> {
+        let start = line.len();
+        let mut ended = false;
+
+        for idx in 0..len {
+            let value = match self.int(kind)? {
+                Int::Value(value) if !ended => value,
+                _ => {
+                    ended = true;
+                    calls.push(GenotypeArray::END);
+                    continue;
+                }
+            };
+            calls.push(value);
+
+            if idx > 0 {
+                line.push(if value & 1 == 1 { '|' } else { '/' });
+            }
+            match value >> 1 {
+                0 => line.push('.'),
+                allele if allele < 0 => return Err("BCF genotype has an invalid allele"),
+                allele => push_int(line, i64::from(allele) - 1),
+            }
+        }
+
+        if line.len() == start {
+            line.push('.');
+        }
+
+        Ok(())
+    }
+}
+
+/// The size in bytes of each element of a typed vector
+fn type_size(kind: u8) -> Result<usize, &'static str> {
+    match kind {
+        TYPE_MISSING | TYPE_INT8 | TYPE_CHAR => Ok(1),
+        TYPE_INT16 => Ok(2),
+        TYPE_INT32 | TYPE_FLOAT => Ok(4),
+        _ => Err("BCF record has an invalid type"),
+    }
+}
+
+/// Append the decimal representation of an integer without going through the
+/// formatting machinery, which is noticeable with thousands of genotypes per
+/// record
+fn push_int(line: &mut String, value: i64) {
+    if value < 0 {
+        line.push('-');
+    }
+
+    let mut value = value.unsigned_abs();
+    let mut digits = [0u8; 20];
+    let mut idx = digits.len();
+
+    loop {
+        idx -= 1;
+        digits[idx] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+
+    for &digit in &digits[idx..] {
+        line.push(digit as char);
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, &'static str> {
+    let mut bytes = [0u8; 4];
+    reader
+        .read_exact(&mut bytes)
+        .map_err(|_| "Error reading the BCF header")?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use vcf::header::{Header, Value};
+    use vcf::stream::from_text_stream;
+
+    const TEXT: &str = "##fileformat=VCFv4.2
+##contig=<ID=1>
+##contig=<ID=X>
+##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">
+##INFO=<ID=AF,Number=A,Type=Float,Description=\"Frequency\">
+##INFO=<ID=DB,Number=0,Type=Flag,Description=\"dbSNP\">
+##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tA\tB\tC
+1\t100\trs1\tA\tG,T\t50\tPASS\tDP=10;AF=0.5,0.25;DB\tGT\t0/1\t1|2\t./.
+X\t200\t.\tC\tA\t.\t.\tDP=3\tGT\t1\t0\t.
+";
+
+    /// A typed value descriptor
+    fn descriptor(kind: u8, len: usize) -> Vec<u8> {
+        vec![(len as u8) << 4 | kind]
+    }
+
+    fn typed_int(value: i8) -> Vec<u8> {
+        let mut bytes = descriptor(TYPE_INT8, 1);
+        bytes.push(value as u8);
+        bytes
+    }
+
+    fn typed_str(value: &str) -> Vec<u8> {
+        let mut bytes = descriptor(TYPE_CHAR, value.len());
+        bytes.extend_from_slice(value.as_bytes());
+        bytes
+    }
+
+    fn record(
+        fixed: [i32; 3],
+        qual: u32,
+        counts: [u32; 2],
+        rest: &[Vec<u8>],
+        gt: &[i8],
+    ) -> Vec<u8> {
+        let mut shared = Vec::new();
+        for value in &fixed {
+            shared.extend_from_slice(&value.to_le_bytes());
+        }
+        shared.extend_from_slice(&qual.to_le_bytes());
+        for value in &counts {
+            shared.extend_from_slice(&value.to_le_bytes());
+        }
+        for bytes in rest {
+            shared.extend_from_slice(bytes);
+        }
+
+        // GT is the fifth string of the dictionary, after PASS and the INFO keys
+        let mut indiv = typed_int(4);
+        indiv.extend(descriptor(TYPE_INT8, 2));
+        indiv.extend(gt.iter().map(|&v| v as u8));
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(shared.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(indiv.len() as u32).to_le_bytes());
+        bytes.extend(shared);
+        bytes.extend(indiv);
+        bytes
+    }
+
+    fn bcf() -> Vec<u8> {
+        let mut header = TEXT.as_bytes().to_vec();
+        header.push(0);
+
+        let mut bytes = b"BCF\x02\x02".to_vec();
+        bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        bytes.extend(header);
+
+        let mut af = descriptor(TYPE_FLOAT, 2);
+        af.extend_from_slice(&0.5f32.to_bits().to_le_bytes());
+        af.extend_from_slice(&0.25f32.to_bits().to_le_bytes());
+        let first = [
+            typed_str("rs1"),
+            typed_str("A"),
+            typed_str("G"),
+            typed_str("T"),
+            typed_int(0),
+            typed_int(1),
+            typed_int(10),
+            typed_int(2),
+            af,
+            typed_int(3),
+            descriptor(TYPE_MISSING, 0),
+        ];
+        // 0/1, 1|2 and ./.
+        let gt = [2, 4, 4, 7, 0, 0];
+        bytes.extend(record(
+            [0, 99, 1],
+            50f32.to_bits(),
+            [3 << 16 | 3, 1 << 24 | 3],
+            &first,
+            &gt,
+        ));
+
+        let second = [
+            typed_str(""),
+            typed_str("C"),
+            typed_str("A"),
+            descriptor(TYPE_INT8, 0),
+            typed_int(1),
+            typed_int(3),
+        ];
+        // Haploid 1, 0 and ., padded to the ploidy of the record
+        let gt = [4, -127, 2, -127, 0, -127];
+        bytes.extend(record(
+            [1, 199, 1],
+            FLOAT_MISSING,
+            [2 << 16 | 1, 1 << 24 | 3],
+            &second,
+            &gt,
+        ));
+
+        bytes
+    }
+
+    #[test]
+    fn decodes_like_text() {
+        let binary = from_reader(Cursor::new(bcf())).unwrap().unfold().unwrap();
+        let text = from_text_stream(Cursor::new(TEXT)).unfold().unwrap();
+        assert_eq!(binary.individuals, text.individuals);
+
+        let header = Header::parse(&text.metadata);
+        let records = binary.stream.zip(text.stream).collect::<Vec<_>>();
+        assert_eq!(records.len(), 2);
+
+        for (binary, text) in records {
+            let (binary, text) = (binary.unwrap(), text.unwrap());
+            assert_eq!(binary.chromosome(), text.chromosome());
+            assert_eq!(binary.position(), text.position());
+            assert_eq!(binary.identifier(), text.identifier());
+            assert_eq!(binary.reference(), text.reference());
+            assert_eq!(binary.alternatives(), text.alternatives());
+            assert_eq!(binary.quality(), text.quality());
+            assert_eq!(binary.filter(), text.filter());
+            for key in &["DP", "AF", "DB"] {
+                assert_eq!(
+                    binary.info_field(&header, key).unwrap(),
+                    text.info_field(&header, key).unwrap()
+                );
+            }
+            assert!(binary.decoded_calls().is_some());
+            assert_eq!(
+                binary.calls().collect::<Vec<_>>(),
+                text.calls().collect::<Vec<_>>()
+            );
+            assert_eq!(
+                binary.genotypes().collect::<Vec<_>>(),
+                text.genotypes().collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn haploid_calls_keep_their_ploidy() {
+        let unfolded = from_reader(Cursor::new(bcf())).unwrap().unfold().unwrap();
+        let data = unfolded.stream.last().unwrap().unwrap();
+        let calls = data.decoded_calls().unwrap();
+
+        assert_eq!(
+            (0..3).map(|idx| calls.ploidy(idx)).collect::<Vec<_>>(),
+            [1, 1, 1]
+        );
+        assert_eq!(calls.dosage(0), Some(1));
+        assert_eq!(calls.dosage(2), None);
+        assert_eq!(
+            data.info_field(&Header::parse(&unfolded.metadata), "DP")
+                .unwrap(),
+            Some(Value::Integer(3))
+        );
+    }
+}
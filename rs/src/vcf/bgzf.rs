@@ -0,0 +1,219 @@
+use std::io::{self, BufRead, Error, ErrorKind, Read, Seek, SeekFrom};
+
+use flate2::{Crc, Decompress, FlushDecompress};
+
+/// The first two bytes of every gzip member
+pub const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The size of the fixed part of a gzip header, up to and including XLEN
+const GZIP_HEADER_LEN: usize = 12;
+
+/// The largest possible BGZF block, compressed or not
+const MAX_BLOCK_SIZE: usize = 0x10000;
+
+/// Whether the given bytes start a gzip member
+pub fn is_gzip(bytes: &[u8]) -> bool {
+    bytes.starts_with(&GZIP_MAGIC)
+}
+
+/// Whether the given bytes start a BGZF block, i.e. a gzip member whose extra
+/// field contains the `BC` subfield holding the size of the block
+pub fn is_bgzf(bytes: &[u8]) -> bool {
+    is_gzip(bytes) && bytes.len() >= 18 && bytes[3] & 4 != 0 && &bytes[12..14] == b"BC"
+}
+
+/// A reader over a BGZF file, a series of concatenated gzip members each
+/// holding at most 64 KiB of uncompressed data.
+///
+/// Positions in the uncompressed stream are expressed as virtual offsets: the
+/// upper 48 bits hold the offset of the start of the compressed block in the
+/// file and the lower 16 bits the offset within the uncompressed block. These
+/// are the offsets stored in tabix and CSI indexes.
+pub struct BgzfReader<R: Read> {
+    inner: R,
+    decompress: Decompress,
+    compressed: Vec<u8>,
+    block: Vec<u8>,
+    position: usize,
+    block_offset: u64,
+    next_block_offset: u64,
+}
+
+impl<R: Read> BgzfReader<R> {
+    pub fn new(inner: R) -> BgzfReader<R> {
+        BgzfReader {
+            inner,
+            decompress: Decompress::new(false),
+            compressed: Vec::with_capacity(MAX_BLOCK_SIZE),
+            block: Vec::with_capacity(MAX_BLOCK_SIZE),
+            position: 0,
+            block_offset: 0,
+            next_block_offset: 0,
+        }
+    }
+
+    /// The virtual offset of the next byte to be read
+    pub fn virtual_offset(&self) -> u64 {
+        if self.position == self.block.len() {
+            self.next_block_offset << 16
+        } else {
+            (self.block_offset << 16) | self.position as u64
+        }
+    }
+
+    /// Read and decompress the next block into the internal buffer. Leaves the
+    /// buffer empty at the end of the file.
+    fn read_block(&mut self) -> io::Result<()> {
+        self.block.clear();
+        self.position = 0;
+        self.block_offset = self.next_block_offset;
+
+        let mut header = [0u8; GZIP_HEADER_LEN];
+        if !read_exact_or_eof(&mut self.inner, &mut header)? {
+            return Ok(());
+        }
+
+        if !is_gzip(&header) || header[2] != 8 || header[3] & 4 == 0 {
+            return Err(invalid_data("Invalid BGZF block header"));
+        }
+
+        let xlen = u16::from(header[10]) as usize | (u16::from(header[11]) as usize) << 8;
+        let mut extra = vec![0u8; xlen];
+        self.inner.read_exact(&mut extra)?;
+
+        let block_size = block_size(&extra)
+            .ok_or_else(|| invalid_data("BGZF block is missing the BC extra field"))?;
+        if block_size < GZIP_HEADER_LEN + xlen + 8 {
+            return Err(invalid_data("BGZF block size is too small"));
+        }
+
+        let cdata_len = block_size - GZIP_HEADER_LEN - xlen - 8;
+        self.compressed.resize(cdata_len + 8, 0);
+        self.inner.read_exact(&mut self.compressed)?;
+
+        let (cdata, trailer) = self.compressed.split_at(cdata_len);
+        let crc = le_u32(&trailer[..4]);
+        let isize = le_u32(&trailer[4..]) as usize;
+
+        self.decompress.reset(false);
+        self.block.reserve(isize);
+        self.decompress
+            .decompress_vec(cdata, &mut self.block, FlushDecompress::Finish)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        if self.block.len() != isize {
+            return Err(invalid_data("BGZF block has the wrong uncompressed size"));
+        }
+
+        let mut checksum = Crc::new();
+        checksum.update(&self.block);
+        if checksum.sum() != crc {
+            return Err(invalid_data("BGZF block has an invalid checksum"));
+        }
+
+        self.next_block_offset += block_size as u64;
+
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> BgzfReader<R> {
+    /// Move the reader to the given virtual offset
+    pub fn seek_virtual(&mut self, offset: u64) -> io::Result<()> {
+        let block_offset = offset >> 16;
+        let position = (offset & 0xffff) as usize;
+
+        if block_offset != self.block_offset || self.block.is_empty() {
+            self.inner.seek(SeekFrom::Start(block_offset))?;
+            self.next_block_offset = block_offset;
+            self.read_block()?;
+        }
+
+        if position > self.block.len() {
+            return Err(invalid_data("Virtual offset is past the end of its block"));
+        }
+
+        self.position = position;
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for BgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = {
+            let available = self.fill_buf()?;
+            let len = available.len().min(buf.len());
+            buf[..len].copy_from_slice(&available[..len]);
+            len
+        };
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: Read> BufRead for BgzfReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // Empty blocks, such as the EOF marker, carry no data, so keep reading
+        // until there is something to return or the file ends
+        while self.position == self.block.len() {
+            self.read_block()?;
+            if self.block.is_empty() && self.block_offset == self.next_block_offset {
+                break;
+            }
+        }
+
+        Ok(&self.block[self.position..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position = (self.position + amt).min(self.block.len());
+    }
+}
+
+/// Find the total size of the block in the `BC` subfield of a gzip extra field
+fn block_size(extra: &[u8]) -> Option<usize> {
+    let mut rest = extra;
+
+    while rest.len() >= 4 {
+        let len = rest[2] as usize | (rest[3] as usize) << 8;
+        if rest.len() < 4 + len {
+            return None;
+        }
+        if &rest[..2] == b"BC" && len == 2 {
+            return Some((rest[4] as usize | (rest[5] as usize) << 8) + 1);
+        }
+        rest = &rest[4 + len..];
+    }
+
+    None
+}
+
+/// Fill the buffer completely, returning `false` if the reader was already at
+/// its end
+fn read_exact_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut read = 0;
+
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) if read == 0 => return Ok(false),
+            Ok(0) => return Err(Error::new(ErrorKind::UnexpectedEof, "Truncated BGZF block")),
+            Ok(len) => read += len,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(true)
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from(bytes[0])
+        | u32::from(bytes[1]) << 8
+        | u32::from(bytes[2]) << 16
+        | u32::from(bytes[3]) << 24
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,312 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use flate2::Crc;
+
+use vcf::error::VcfError;
+use vcf::matrix::{GenotypeMatrix, Variant, CALLS_PER_BYTE, FLAGS_PER_BYTE};
+use vcf::stream::{peek, Peeked};
+
+/// The bytes every cache file starts with
+const MAGIC: &[u8; 4] = b"GTMX";
+
+/// The version of the format written, bumped whenever it changes
+const VERSION: u32 = 1;
+
+/// Whether the given bytes start a cache file
+pub fn is_cache(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Read enough of the start of a reader to tell whether it is a cache file,
+/// and give back a reader that still starts at the beginning
+pub fn peek_cache<R: BufRead>(reader: R) -> io::Result<(bool, Peeked<R>)> {
+    let reader = peek(reader, MAGIC.len())?;
+    Ok((is_cache(reader.start()), reader))
+}
+
+/// Write a genotype matrix in a compact binary format, so that it can be
+/// loaded again much faster than the VCF it came from is parsed.
+///
+/// After the magic bytes and the version, the file has the individuals, the
+/// variants, the packed calls and then the packed haploid flags. Counts and
+/// lengths are little-endian `u32`s, positions `u64`s and strings are prefixed
+/// by their length. The file ends with the CRC-32 of everything before it.
+pub fn write_cache<W: Write>(matrix: &GenotypeMatrix, writer: W) -> io::Result<()> {
+    let mut writer = Checksummed::new(writer);
+
+    writer.write_all(MAGIC)?;
+    write_u32(&mut writer, VERSION)?;
+
+    write_u32(&mut writer, matrix.individual_count() as u32)?;
+    for individual in matrix.individuals() {
+        write_str(&mut writer, individual)?;
+    }
+
+    write_u32(&mut writer, matrix.variant_count() as u32)?;
+    for variant in matrix.variants() {
+        write_str(&mut writer, &variant.chromosome)?;
+        writer.write_all(&variant.position.to_le_bytes())?;
+        write_str(&mut writer, &variant.identifier)?;
+        write_str(&mut writer, &variant.reference)?;
+        write_u32(&mut writer, variant.alternatives.len() as u32)?;
+        for alternative in &variant.alternatives {
+            write_str(&mut writer, alternative)?;
+        }
+    }
+
+    writer.write_all(matrix.packed())?;
+    writer.write_all(matrix.packed_haploid())?;
+
+    let checksum = writer.crc.sum();
+    let mut writer = writer.inner;
+    write_u32(&mut writer, checksum)?;
+    writer.flush()
+}
+
+/// Read a genotype matrix written by `write_cache`, checking its version, its
+/// checksum and that nothing follows it
+pub fn read_cache<R: Read>(reader: R) -> Result<GenotypeMatrix, VcfError> {
+    let mut reader = Checksummed::new(reader);
+
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if !is_cache(&magic) {
+        return Err("Not a genotype cache file".into());
+    }
+
+    let version = read_u32(&mut reader)?;
+    if version != VERSION {
+        let message = format!(
+            "Genotype cache has version {}, but only version {} is supported",
+            version, VERSION
+        );
+        return Err(VcfError::Invalid(message));
+    }
+
+    let count = read_u32(&mut reader)?;
+    let individuals = (0..count)
+        .map(|_| read_string(&mut reader))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let count = read_u32(&mut reader)?;
+    let mut variants = Vec::new();
+    for _ in 0..count {
+        let chromosome = read_string(&mut reader)?;
+        let mut position = [0; 8];
+        reader.read_exact(&mut position)?;
+        let identifier = read_string(&mut reader)?;
+        let reference = read_string(&mut reader)?;
+        let alternatives = (0..read_u32(&mut reader)?)
+            .map(|_| read_string(&mut reader))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        variants.push(Variant {
+            chromosome,
+            position: u64::from_le_bytes(position),
+            identifier,
+            reference,
+            alternatives,
+        });
+    }
+
+    let stride = individuals.len().div_ceil(CALLS_PER_BYTE);
+    let calls = read_bytes(&mut reader, stride * variants.len())?;
+    let stride = individuals.len().div_ceil(FLAGS_PER_BYTE);
+    let haploid = read_bytes(&mut reader, stride * variants.len())?;
+
+    let computed = reader.crc.sum();
+    let checksum = read_u32(&mut reader.inner)?;
+    if checksum != computed {
+        return Err("Genotype cache is corrupt: its checksum does not match".into());
+    }
+    if reader.inner.read(&mut [0])? != 0 {
+        return Err("Genotype cache is corrupt: it has bytes after its checksum".into());
+    }
+
+    Ok(GenotypeMatrix::from_packed(
+        individuals,
+        variants,
+        calls,
+        haploid,
+    ))
+}
+
+/// Write a genotype matrix to a cache file at the given path
+pub fn save<P: AsRef<Path>>(matrix: &GenotypeMatrix, path: P) -> io::Result<()> {
+    write_cache(matrix, BufWriter::new(File::create(path)?))
+}
+
+/// Read a genotype matrix from the cache file at the given path
+pub fn load<P: AsRef<Path>>(path: P) -> Result<GenotypeMatrix, VcfError> {
+    read_cache(BufReader::new(File::open(path)?))
+}
+
+/// Computes the CRC-32 of the bytes read or written through it
+struct Checksummed<T> {
+    inner: T,
+    crc: Crc,
+}
+
+impl<T> Checksummed<T> {
+    fn new(inner: T) -> Checksummed<T> {
+        Checksummed {
+            inner,
+            crc: Crc::new(),
+        }
+    }
+}
+
+impl<R: Read> Read for Checksummed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.crc.update(&buf[..count]);
+        Ok(count)
+    }
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.crc.update(&buf[..count]);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_str<W: Write>(writer: &mut W, value: &str) -> io::Result<()> {
+    write_u32(writer, value.len() as u32)?;
+    writer.write_all(value.as_bytes())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Read `len` bytes through `take`, so that a corrupt count cannot make us
+/// allocate more than the file holds
+fn read_bytes<R: Read>(reader: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    Ok(bytes)
+}
+
+fn read_string<R: Read>(reader: &mut R) -> Result<String, VcfError> {
+    let len = read_u32(reader)? as usize;
+    let bytes = read_bytes(reader, len)?;
+
+    String::from_utf8(bytes).map_err(|_| "Genotype cache has a string that is not UTF-8".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn variant(position: u64) -> Variant {
+        Variant {
+            chromosome: "X".to_string(),
+            position,
+            identifier: format!("rs{}", position),
+            reference: "A".to_string(),
+            alternatives: vec!["G".to_string()],
+        }
+    }
+
+    /// Nine individuals, so that both the calls and the haploid flags have a
+    /// partly used last byte
+    fn matrix() -> GenotypeMatrix {
+        let individuals = (0..9).map(|idx| format!("I{}", idx)).collect();
+        let mut matrix = GenotypeMatrix::new(individuals);
+        matrix.push_calls(
+            variant(100),
+            vec![
+                (Some(0), 2),
+                (Some(1), 2),
+                (Some(2), 2),
+                (None, 2),
+                (Some(0), 1),
+            ],
+        );
+        matrix.push_calls(
+            variant(200),
+            (0..9).map(|idx| {
+                if idx % 3 == 0 {
+                    (None, 1)
+                } else {
+                    (Some(1), 1)
+                }
+            }),
+        );
+        matrix
+    }
+
+    fn written() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_cache(&matrix(), &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = env::temp_dir().join(format!("rs-cache-{}.gtmx", ::std::process::id()));
+        let expected = matrix();
+        save(&expected, &path).unwrap();
+        let loaded = load(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!(loaded.individuals(), expected.individuals());
+        assert_eq!(loaded.variants(), expected.variants());
+        for variant in 0..2 {
+            for individual in 0..9 {
+                assert_eq!(
+                    loaded.get(variant, individual),
+                    expected.get(variant, individual)
+                );
+                assert_eq!(
+                    loaded.ploidy(variant, individual),
+                    expected.ploidy(variant, individual)
+                );
+            }
+        }
+        assert_eq!(loaded.get(0, 3), None);
+        assert_eq!(loaded.ploidy(0, 4), 1);
+        assert_eq!(loaded.get(1, 8), Some(1));
+        assert_eq!(loaded.ploidy(1, 8), 1);
+    }
+
+    #[test]
+    fn rejects_corrupt_checksums() {
+        let mut bytes = written();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(read_cache(&bytes[..]).is_err());
+
+        let mut bytes = written();
+        bytes[MAGIC.len() + 4] ^= 1;
+        assert!(read_cache(&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        let mut bytes = written();
+        assert!(read_cache(&bytes[..]).is_ok());
+        bytes.push(0);
+        assert!(read_cache(&bytes[..]).is_err());
+    }
+}
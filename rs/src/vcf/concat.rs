@@ -0,0 +1,240 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use vcf::error::VcfError;
+use vcf::stream::{
+    decompress, DataStream, DataStreamFromTextStream, IndividualsReader,
+    IndividualsReaderFromTextStream, MetadataReader, MetadataReaderFromTextStream, VCFData,
+};
+
+/// The metadata keys whose lines must be the same in every file by default
+const DEFAULT_KEYS: [&str; 5] = ["fileformat", "INFO", "FORMAT", "FILTER", "ALT"];
+
+/// What to do when the headers of the files being concatenated differ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MismatchPolicy {
+    /// Fail to read the stream
+    Error,
+    /// Print a warning and continue
+    Warn,
+    /// Silently continue
+    Ignore,
+}
+
+/// A stream over several VCF files, such as one per chromosome, read one after
+/// the other as if they were a single file.
+///
+/// The files must have the same individuals, in the same order. The metadata
+/// lines with the given keys must also be the same, although not necessarily
+/// in the same order; what happens if they are not is decided by the policy.
+/// The metadata of the stream is that of the first file, plus the `contig`
+/// lines of the other files that it lacks.
+pub struct Concat {
+    paths: Vec<PathBuf>,
+    keys: Vec<String>,
+    policy: MismatchPolicy,
+}
+
+impl Concat {
+    pub fn new<I, P>(paths: I) -> Concat
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        Concat {
+            paths: paths.into_iter().map(|p| p.into()).collect(),
+            keys: DEFAULT_KEYS.iter().map(|k| k.to_string()).collect(),
+            policy: MismatchPolicy::Error,
+        }
+    }
+
+    /// Set the metadata keys whose lines must match across files
+    pub fn keys<I, K>(mut self, keys: I) -> Concat
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<String>,
+    {
+        self.keys = keys.into_iter().map(|k| k.into()).collect();
+        self
+    }
+
+    /// Set what to do when the metadata lines of the files differ
+    pub fn policy(mut self, policy: MismatchPolicy) -> Concat {
+        self.policy = policy;
+        self
+    }
+}
+
+type Input = Box<dyn BufRead>;
+
+impl MetadataReader for Concat {
+    type Next = IndividualsReaderFromConcat;
+
+    fn read_metadata(self, map: &mut Vec<(String, String)>) -> Result<Self::Next, VcfError> {
+        if self.paths.is_empty() {
+            return Err("No files to concatenate".into());
+        }
+
+        let mut readers = Vec::with_capacity(self.paths.len());
+        let mut first = Vec::new();
+
+        for (idx, path) in self.paths.iter().enumerate() {
+            let in_file = |e: VcfError| e.in_file(path);
+
+            let file = File::open(path).map_err(|e| in_file(e.into()))?;
+            let reader = decompress(BufReader::new(file)).map_err(|e| in_file(e.into()))?;
+
+            let mut metadata = Vec::new();
+            let next = MetadataReaderFromTextStream::new(reader)
+                .read_metadata(&mut metadata)
+                .map_err(in_file)?;
+            readers.push(next);
+
+            if idx == 0 {
+                first = metadata;
+                continue;
+            }
+
+            let expected = relevant(&first, &self.keys);
+            let found = relevant(&metadata, &self.keys);
+            if let Some(&(key, value)) = found.symmetric_difference(&expected).next() {
+                let message = format!(
+                    "Header differs from the one of {}, e.g. in ##{}={}",
+                    self.paths[0].display(),
+                    key,
+                    value
+                );
+                mismatch(self.policy, VcfError::Invalid(message).in_file(path))?;
+            }
+
+            // Each file may only declare its own contigs
+            let known = first.iter().cloned().collect::<HashSet<_>>();
+            let missing = metadata
+                .into_iter()
+                .filter(|(key, _)| key == "contig")
+                .filter(|line| !known.contains(line))
+                .collect::<Vec<_>>();
+            first.extend(missing);
+        }
+
+        map.extend(first);
+
+        Ok(IndividualsReaderFromConcat {
+            paths: self.paths,
+            readers,
+        })
+    }
+}
+
+pub struct IndividualsReaderFromConcat {
+    paths: Vec<PathBuf>,
+    readers: Vec<IndividualsReaderFromTextStream<Input>>,
+}
+
+impl IndividualsReader for IndividualsReaderFromConcat {
+    type Next = DataStreamFromConcat;
+
+    fn read_individuals(self, list: &mut Vec<String>) -> Result<Self::Next, VcfError> {
+        let mut streams = Vec::with_capacity(self.readers.len());
+        let mut first: Option<Vec<String>> = None;
+
+        for (reader, path) in self.readers.into_iter().zip(&self.paths) {
+            let mut individuals = Vec::new();
+            let stream = reader
+                .read_individuals(&mut individuals)
+                .map_err(|e| e.in_file(path))?;
+            streams.push(stream);
+
+            match first {
+                None => first = Some(individuals),
+                Some(ref first) if *first != individuals => {
+                    let message = format!(
+                        "Individuals differ from the ones of {}",
+                        self.paths[0].display()
+                    );
+                    return Err(VcfError::Invalid(message).in_file(path));
+                }
+                Some(_) => {}
+            }
+        }
+
+        list.extend(first.unwrap_or_default());
+
+        streams.reverse();
+
+        Ok(DataStreamFromConcat {
+            paths: self.paths,
+            streams,
+            current: 0,
+        })
+    }
+}
+
+pub struct DataStreamFromConcat {
+    paths: Vec<PathBuf>,
+
+    // Kept in reverse order, so that the next one can be popped
+    streams: Vec<DataStreamFromTextStream<Input>>,
+    current: usize,
+}
+
+impl DataStreamFromConcat {
+    /// The path of the file the last record came from
+    pub fn source(&self) -> &Path {
+        &self.paths[self.current]
+    }
+
+    /// Pair every record with the index, in the list given to `Concat::new`,
+    /// of the file it came from
+    pub fn with_sources(self) -> impl Iterator<Item = (usize, Result<VCFData, VcfError>)> {
+        let mut stream = self;
+        ::std::iter::from_fn(move || {
+            let data = stream.next()?;
+            Some((stream.current, data))
+        })
+    }
+}
+
+impl Iterator for DataStreamFromConcat {
+    type Item = Result<VCFData, VcfError>;
+
+    fn next(&mut self) -> Option<Result<VCFData, VcfError>> {
+        loop {
+            let data = self.streams.last_mut()?.next();
+            match data {
+                Some(Ok(data)) => return Some(Ok(data)),
+                Some(Err(e)) => return Some(Err(e.in_file(&self.paths[self.current]))),
+                None => {
+                    self.streams.pop();
+                    if !self.streams.is_empty() {
+                        self.current += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl DataStream for DataStreamFromConcat {}
+
+/// The metadata lines with one of the given keys
+fn relevant<'a>(metadata: &'a [(String, String)], keys: &[String]) -> HashSet<(&'a str, &'a str)> {
+    metadata
+        .iter()
+        .filter(|(key, _)| keys.contains(key))
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect()
+}
+
+fn mismatch(policy: MismatchPolicy, error: VcfError) -> Result<(), VcfError> {
+    match policy {
+        MismatchPolicy::Error => Err(error),
+        MismatchPolicy::Warn => {
+            eprintln!("Warning: {}", error);
+            Ok(())
+        }
+        MismatchPolicy::Ignore => Ok(()),
+    }
+}
@@ -0,0 +1,163 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// The longest offending text kept in an error, since data lines can be huge
+const MAX_TEXT_LEN: usize = 80;
+
+/// An error while reading a VCF stream
+#[derive(Debug)]
+pub enum VcfError {
+    /// Reading the underlying input failed
+    Io {
+        line: Option<usize>,
+        error: io::Error,
+    },
+    /// A line of the input is malformed. Lines and columns are 1-based; the
+    /// line is unknown when reading from an arbitrary position, such as in a
+    /// region query, and is the record number in binary formats.
+    Syntax {
+        line: Option<usize>,
+        column: Option<usize>,
+        text: String,
+        message: String,
+    },
+    /// The input is invalid as a whole
+    Invalid(String),
+    /// An error in one of several input files
+    File { path: PathBuf, error: Box<VcfError> },
+}
+
+impl VcfError {
+    pub fn io(line: Option<usize>, error: io::Error) -> VcfError {
+        VcfError::Io { line, error }
+    }
+
+    pub fn syntax<S: Into<String>>(
+        line: Option<usize>,
+        column: Option<usize>,
+        text: &str,
+        message: S,
+    ) -> VcfError {
+        let text = match text.char_indices().nth(MAX_TEXT_LEN) {
+            Some((idx, _)) => format!("{}...", &text[..idx]),
+            None => text.to_string(),
+        };
+
+        VcfError::Syntax {
+            line,
+            column,
+            text,
+            message: message.into(),
+        }
+    }
+
+    /// Attach the path of the file in which the error occurred
+    pub fn in_file<P: Into<PathBuf>>(self, path: P) -> VcfError {
+        VcfError::File {
+            path: path.into(),
+            error: Box::new(self),
+        }
+    }
+
+    /// Set the line at which the error occurred, unless it is known already,
+    /// as for errors found in a record after it was read
+    pub fn at_line(self, line: usize) -> VcfError {
+        match self {
+            VcfError::Io { line: None, error } => VcfError::io(Some(line), error),
+            VcfError::Syntax {
+                line: None,
+                column,
+                text,
+                message,
+            } => VcfError::Syntax {
+                line: Some(line),
+                column,
+                text,
+                message,
+            },
+            VcfError::File { path, error } => VcfError::File {
+                path,
+                error: Box::new(error.at_line(line)),
+            },
+            error => error,
+        }
+    }
+
+    /// The line at which the error occurred, if known
+    pub fn line(&self) -> Option<usize> {
+        match *self {
+            VcfError::Io { line, .. } | VcfError::Syntax { line, .. } => line,
+            VcfError::Invalid(_) => None,
+            VcfError::File { ref error, .. } => error.line(),
+        }
+    }
+}
+
+impl fmt::Display for VcfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VcfError::Io { line, ref error } => {
+                if let Some(line) = line {
+                    write!(f, "line {}: ", line)?;
+                }
+                write!(f, "{}", error)
+            }
+            VcfError::Syntax {
+                line,
+                column,
+                ref text,
+                ref message,
+            } => {
+                if let Some(line) = line {
+                    write!(f, "line {}: ", line)?;
+                }
+                if let Some(column) = column {
+                    write!(f, "column {}: ", column)?;
+                }
+                write!(f, "{}", message)?;
+                if !text.is_empty() {
+                    write!(f, " ({:?})", text)?;
+                }
+                Ok(())
+            }
+            VcfError::Invalid(ref message) => write!(f, "{}", message),
+            VcfError::File {
+                ref path,
+                ref error,
+            } => write!(f, "{}: {}", path.display(), error),
+        }
+    }
+}
+
+impl Error for VcfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            VcfError::Io { ref error, .. } => Some(error),
+            VcfError::File { ref error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for VcfError {
+    fn from(error: io::Error) -> VcfError {
+        VcfError::io(None, error)
+    }
+}
+
+impl From<&'static str> for VcfError {
+    fn from(message: &'static str) -> VcfError {
+        VcfError::Invalid(message.to_string())
+    }
+}
+
+impl From<VcfError> for io::Error {
+    fn from(error: VcfError) -> io::Error {
+        match error {
+            VcfError::Io { line: None, error } => error,
+            error => io::Error::new(io::ErrorKind::InvalidData, error),
+        }
+    }
+}
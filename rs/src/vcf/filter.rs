@@ -1,10 +1,14 @@
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
+use std::mem;
+use vcf::error::VcfError;
 use vcf::stream::{DataStream, IndividualsReader, MetadataReader, VCFData};
 
 pub enum DataAction {
     Ignore,
     Stop,
     Data(VCFData),
+    /// Replace the item with several ones, produced in the given order
+    Split(Vec<VCFData>),
 }
 
 pub trait Filter {
@@ -51,7 +55,7 @@ impl<S: MetadataReader, F: Filter> MetadataReader for MetadataReaderFromFilter<S
     fn read_metadata(
         mut self,
         map: &mut Vec<(String, String)>,
-    ) -> Result<Self::Next, &'static str> {
+    ) -> Result<Self::Next, VcfError> {
         let mut inner_map = Vec::new();
         let next = self.inner.read_metadata(&mut inner_map)?;
         map.extend(self.filter.filter_metadata(inner_map));
@@ -71,7 +75,7 @@ struct IndividualsReaderFromFilter<S: IndividualsReader, F: Filter> {
 impl<S: IndividualsReader, F: Filter> IndividualsReader for IndividualsReaderFromFilter<S, F> {
     type Next = DataStreamFromFilter<S::Next, F>;
 
-    fn read_individuals(mut self, list: &mut Vec<String>) -> Result<Self::Next, &'static str> {
+    fn read_individuals(mut self, list: &mut Vec<String>) -> Result<Self::Next, VcfError> {
         let mut inner_list = Vec::new();
         let next = self.inner.read_individuals(&mut inner_list)?;
         list.extend(self.filter.filter_individuals(inner_list));
@@ -79,6 +83,7 @@ impl<S: IndividualsReader, F: Filter> IndividualsReader for IndividualsReaderFro
         Ok(DataStreamFromFilter {
             inner: next,
             filter: self.filter,
+            pending: VecDeque::new(),
         })
     }
 }
@@ -86,23 +91,59 @@ impl<S: IndividualsReader, F: Filter> IndividualsReader for IndividualsReaderFro
 struct DataStreamFromFilter<S: DataStream, F: Filter> {
     inner: S,
     filter: F,
+    pending: VecDeque<VCFData>,
 }
 
 impl<S: DataStream, F: Filter> Iterator for DataStreamFromFilter<S, F> {
-    type Item = VCFData;
+    type Item = Result<VCFData, VcfError>;
 
-    fn next(&mut self) -> Option<VCFData> {
+    fn next(&mut self) -> Option<Result<VCFData, VcfError>> {
         loop {
-            match self.inner.next().map(|data| self.filter.filter_item(data))? {
+            if let Some(data) = self.pending.pop_front() {
+                return Some(Ok(data));
+            }
+
+            let data = match self.inner.next()? {
+                Ok(data) => data,
+                Err(e) => return Some(Err(e)),
+            };
+
+            match self.filter.filter_item(data) {
                 DataAction::Ignore => continue,
                 DataAction::Stop => return None,
-                DataAction::Data(data) => return Some(data),
+                DataAction::Data(data) => return Some(Ok(data)),
+                DataAction::Split(data) => self.pending.extend(data),
             }
         }
     }
 }
 
-impl<S: DataStream, F: Filter> DataStream for DataStreamFromFilter<S, F> {}
+impl<S: DataStream, F: Filter> DataStream for DataStreamFromFilter<S, F> {
+    fn read_into(&mut self, data: &mut VCFData) -> Option<Result<(), VcfError>> {
+        loop {
+            if let Some(next) = self.pending.pop_front() {
+                *data = next;
+                return Some(Ok(()));
+            }
+
+            if let Err(e) = self.inner.read_into(data)? {
+                return Some(Err(e));
+            }
+
+            // Filters that keep the record hand back its storage, so it is
+            // reused for the next one
+            match self.filter.filter_item(mem::take(data)) {
+                DataAction::Ignore => continue,
+                DataAction::Stop => return None,
+                DataAction::Data(next) => {
+                    *data = next;
+                    return Some(Ok(()));
+                }
+                DataAction::Split(next) => self.pending.extend(next),
+            }
+        }
+    }
+}
 
 pub struct IndividualsFilter {
     individuals: HashSet<String>,
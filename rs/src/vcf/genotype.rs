@@ -0,0 +1,232 @@
+use std::str::FromStr;
+
+use vcf::population::Sex;
+
+/// The pseudoautosomal regions of chromosome X in GRCh37, the assembly of the
+/// 1000 Genomes data, where males are diploid
+const PSEUDOAUTOSOMAL_X: [(u64, u64); 2] = [(60_001, 2_699_520), (154_931_044, 155_260_560)];
+
+/// A genotype call, as written in the GT field of a sample, such as `0|1`,
+/// `1/2`, `./.` or `0` for haploid calls
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Genotype {
+    alleles: Vec<Option<usize>>,
+    phased: bool,
+}
+
+impl Genotype {
+    pub fn new(alleles: Vec<Option<usize>>, phased: bool) -> Genotype {
+        Genotype { alleles, phased }
+    }
+
+    /// The index of each allele in the call, where 0 is the reference and
+    /// `None` is missing
+    pub fn alleles(&self) -> &[Option<usize>] {
+        &self.alleles
+    }
+
+    pub fn ploidy(&self) -> usize {
+        self.alleles.len()
+    }
+
+    /// Whether the alleles are separated by `|`. Haploid calls count as
+    /// phased.
+    pub fn is_phased(&self) -> bool {
+        self.phased
+    }
+
+    /// Whether no allele was called
+    pub fn is_missing(&self) -> bool {
+        self.alleles.iter().all(Option::is_none)
+    }
+
+    /// Whether some, but maybe not all, alleles were not called
+    pub fn has_missing(&self) -> bool {
+        self.alleles.iter().any(Option::is_none)
+    }
+
+    /// The largest allele index in the call, 2 or more meaning the call
+    /// involves a second alternate allele of a multi-allelic site
+    pub fn max_allele(&self) -> Option<usize> {
+        self.alleles.iter().filter_map(|&a| a).max()
+    }
+
+    /// The number of copies of the given allele, or `None` if any allele is
+    /// missing
+    pub fn allele_count(&self, allele: usize) -> Option<usize> {
+        if self.has_missing() {
+            return None;
+        }
+        Some(self.alleles.iter().filter(|&&a| a == Some(allele)).count())
+    }
+
+    /// The number of non-reference alleles, or `None` if any allele is missing
+    pub fn dosage(&self) -> Option<usize> {
+        if self.has_missing() {
+            return None;
+        }
+        Some(self.alleles.iter().filter(|&&a| a != Some(0)).count())
+    }
+
+    /// The fraction of non-reference alleles, or `None` if any allele is
+    /// missing
+    pub fn alt_fraction(&self) -> Option<f32> {
+        Some(self.dosage()? as f32 / self.ploidy() as f32)
+    }
+}
+
+impl FromStr for Genotype {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Genotype, &'static str> {
+        if s.is_empty() {
+            return Err("Genotype is empty");
+        }
+
+        let phased = !s.contains('/');
+        let alleles = s
+            .split(['/', '|'])
+            .map(|allele| match allele {
+                "." => Ok(None),
+                _ => allele
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| "Genotype allele is not a number"),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Genotype { alleles, phased })
+    }
+}
+
+/// The genotype calls of all the samples of a record, as the typed values of
+/// BCF: each allele is `(index + 1) << 1 | phased` and 0 is a missing allele,
+/// with calls of lower ploidy padded at the end. Calls can be inspected
+/// without allocating a `Genotype` for each sample.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GenotypeArray {
+    ploidy: usize,
+    values: Vec<i32>,
+}
+
+impl GenotypeArray {
+    /// The value that pads calls of lower ploidy
+    pub const END: i32 = -1;
+
+    /// Create an array with `ploidy` values per sample, one sample after the
+    /// other
+    pub fn new(ploidy: usize, values: Vec<i32>) -> GenotypeArray {
+        GenotypeArray { ploidy, values }
+    }
+
+    /// The number of samples
+    pub fn len(&self) -> usize {
+        match self.ploidy {
+            0 => 0,
+            ploidy => self.values.len() / ploidy,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The raw values of a sample, without padding
+    fn sample(&self, sample: usize) -> &[i32] {
+        let values = &self.values[sample * self.ploidy..(sample + 1) * self.ploidy];
+        let len = values.iter().take_while(|&&v| v != Self::END).count();
+        &values[..len]
+    }
+
+    /// The call of a sample, as `Genotype::from_str` would parse its text
+    pub fn get(&self, sample: usize) -> Option<Genotype> {
+        if sample >= self.len() {
+            return None;
+        }
+
+        let values = self.sample(sample);
+        if values.is_empty() {
+            return Some(Genotype::new(vec![None], true));
+        }
+
+        let alleles = values.iter().map(|&v| allele(v)).collect();
+        let phased = values[1..].iter().all(|&v| v & 1 == 1);
+        Some(Genotype::new(alleles, phased))
+    }
+
+    /// The number of alleles of the call of a sample
+    pub fn ploidy(&self, sample: usize) -> usize {
+        self.sample(sample).len().max(1)
+    }
+
+    /// The number of non-reference alleles of a sample, or `None` if any
+    /// allele is missing
+    pub fn dosage(&self, sample: usize) -> Option<usize> {
+        let values = self.sample(sample);
+        if values.is_empty() {
+            return None;
+        }
+
+        let mut dosage = 0;
+        for &value in values {
+            dosage += (allele(value)? != 0) as usize;
+        }
+        Some(dosage)
+    }
+
+    /// The largest allele index in the call of a sample, as for
+    /// `Genotype::max_allele`
+    pub fn max_allele(&self, sample: usize) -> Option<usize> {
+        self.sample(sample).iter().filter_map(|&v| allele(v)).max()
+    }
+
+    /// The fraction of non-reference alleles of a sample, or `None` if any
+    /// allele is missing
+    pub fn alt_fraction(&self, sample: usize) -> Option<f32> {
+        Some(self.dosage(sample)? as f32 / self.ploidy(sample) as f32)
+    }
+
+    /// Keep only the samples at the given indices, in their order
+    pub(crate) fn keep<I: IntoIterator<Item = usize>>(&self, indices: I) -> GenotypeArray {
+        let mut values = Vec::new();
+        for idx in indices {
+            values.extend_from_slice(&self.values[idx * self.ploidy..(idx + 1) * self.ploidy]);
+        }
+
+        GenotypeArray {
+            ploidy: self.ploidy,
+            values,
+        }
+    }
+}
+
+fn allele(value: i32) -> Option<usize> {
+    match value >> 1 {
+        0 => None,
+        allele => Some(allele as usize - 1),
+    }
+}
+
+/// The number of copies of a position an individual of the given sex carries:
+/// one of chrX outside the pseudoautosomal regions and of chrY in males, none
+/// of chrY in females and one of the mitochondrial genome. Returns `None` when
+/// it depends on an unknown sex.
+pub fn expected_ploidy(contig: &str, position: u64, sex: Sex) -> Option<usize> {
+    let contig = contig.trim_start_matches("chr");
+
+    match (contig, sex) {
+        ("MT", _) | ("M", _) => Some(1),
+        ("X", Sex::Male) if !is_pseudoautosomal(position) => Some(1),
+        ("X", Sex::Unknown) if !is_pseudoautosomal(position) => None,
+        ("Y", Sex::Male) => Some(1),
+        ("Y", Sex::Female) => Some(0),
+        ("Y", Sex::Unknown) => None,
+        _ => Some(2),
+    }
+}
+
+fn is_pseudoautosomal(position: u64) -> bool {
+    PSEUDOAUTOSOMAL_X
+        .iter()
+        .any(|&(start, end)| start <= position && position <= end)
+}
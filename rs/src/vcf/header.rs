@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// The number of values of an INFO or FORMAT field
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Number {
+    /// A fixed number of values
+    Count(usize),
+    /// One value per alternate allele (`A`)
+    AlternateAlleles,
+    /// One value per allele, including the reference (`R`)
+    Alleles,
+    /// One value per possible genotype (`G`)
+    Genotypes,
+    /// An unknown or varying number of values (`.`)
+    Unknown,
+}
+
+impl Number {
+    /// The number of values expected in a record with the given number of
+    /// alternate alleles and ploidy, if known
+    pub fn expected(&self, alternatives: usize, ploidy: usize) -> Option<usize> {
+        match *self {
+            Number::Count(count) => Some(count),
+            Number::AlternateAlleles => Some(alternatives),
+            Number::Alleles => Some(alternatives + 1),
+            Number::Genotypes => {
+                // The number of multisets of size `ploidy` taken from the
+                // alleles
+                let alleles = alternatives + 1;
+                let mut count = 1;
+                for k in 0..ploidy {
+                    count = count * (alleles + k) / (k + 1);
+                }
+                Some(count)
+            }
+            Number::Unknown => None,
+        }
+    }
+}
+
+impl FromStr for Number {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Number, &'static str> {
+        match s {
+            "A" => Ok(Number::AlternateAlleles),
+            "R" => Ok(Number::Alleles),
+            "G" => Ok(Number::Genotypes),
+            "." => Ok(Number::Unknown),
+            _ => s
+                .parse()
+                .map(Number::Count)
+                .map_err(|_| "Header definition has an invalid Number"),
+        }
+    }
+}
+
+/// The type of the values of an INFO or FORMAT field
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    Integer,
+    Float,
+    Flag,
+    Character,
+    String,
+}
+
+impl FromStr for ValueType {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<ValueType, &'static str> {
+        match s {
+            "Integer" => Ok(ValueType::Integer),
+            "Float" => Ok(ValueType::Float),
+            "Flag" => Ok(ValueType::Flag),
+            "Character" => Ok(ValueType::Character),
+            "String" => Ok(ValueType::String),
+            _ => Err("Header definition has an invalid Type"),
+        }
+    }
+}
+
+/// A value of an INFO or FORMAT field, converted according to its definition.
+/// Fields with a single value are scalars; missing elements of arrays are
+/// `None`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value<'a> {
+    Flag,
+    Integer(i32),
+    Float(f32),
+    Character(char),
+    String(&'a str),
+    IntegerArray(Vec<Option<i32>>),
+    FloatArray(Vec<Option<f32>>),
+    CharacterArray(Vec<Option<char>>),
+    StringArray(Vec<Option<&'a str>>),
+}
+
+impl<'a> Value<'a> {
+    pub fn is_flag(&self) -> bool {
+        *self == Value::Flag
+    }
+
+    pub fn as_str(&self) -> Option<&'a str> {
+        match *self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The integers in this value, as a one-element vector for scalars
+    pub fn integers(&self) -> Option<Vec<Option<i32>>> {
+        match *self {
+            Value::Integer(v) => Some(vec![Some(v)]),
+            Value::IntegerArray(ref vs) => Some(vs.clone()),
+            _ => None,
+        }
+    }
+
+    /// The floats in this value, as a one-element vector for scalars
+    pub fn floats(&self) -> Option<Vec<Option<f32>>> {
+        match *self {
+            Value::Float(v) => Some(vec![Some(v)]),
+            Value::FloatArray(ref vs) => Some(vs.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// The definition of an INFO or FORMAT field
+#[derive(Clone, Debug)]
+pub struct FieldDefinition {
+    pub id: String,
+    pub number: Number,
+    pub value_type: ValueType,
+    pub description: String,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl FieldDefinition {
+    fn parse(attributes: Vec<(String, String)>) -> Result<FieldDefinition, &'static str> {
+        let number = attribute(&attributes, "Number")
+            .ok_or("Header definition has no Number")?
+            .parse()?;
+        let value_type = attribute(&attributes, "Type")
+            .ok_or("Header definition has no Type")?
+            .parse()?;
+
+        Ok(FieldDefinition {
+            id: id(&attributes)?,
+            number,
+            value_type,
+            description: attribute(&attributes, "Description")
+                .unwrap_or_default()
+                .to_string(),
+            attributes,
+        })
+    }
+
+    /// Convert the raw text of a field into a typed value. `raw` is `None` for
+    /// INFO keys without a value, which is how flags are written. Returns
+    /// `None` when the value is missing.
+    pub fn parse_value<'a>(
+        &self,
+        raw: Option<&'a str>,
+        alternatives: usize,
+        ploidy: usize,
+    ) -> Result<Option<Value<'a>>, &'static str> {
+        let raw = match (self.value_type, raw) {
+            (ValueType::Flag, None) => return Ok(Some(Value::Flag)),
+            (ValueType::Flag, Some(_)) => return Err("Flag field has a value"),
+            (_, None) => return Err("Field has no value"),
+            (_, Some(".")) => return Ok(None),
+            (_, Some(raw)) => raw,
+        };
+
+        let count = raw.split(',').count();
+        if let Some(expected) = self.number.expected(alternatives, ploidy) {
+            if count != expected {
+                return Err("Field has the wrong number of values");
+            }
+        }
+
+        let scalar = self.number == Number::Count(1);
+
+        let value = match self.value_type {
+            ValueType::Integer => {
+                let values = parse_array(raw, |v| v.parse::<i32>().ok())?;
+                if scalar {
+                    values[0].map(Value::Integer)
+                } else {
+                    Some(Value::IntegerArray(values))
+                }
+            }
+            ValueType::Float => {
+                let values = parse_array(raw, |v| v.parse::<f32>().ok())?;
+                if scalar {
+                    values[0].map(Value::Float)
+                } else {
+                    Some(Value::FloatArray(values))
+                }
+            }
+            ValueType::Character => {
+                let values = parse_array(raw, |v| {
+                    let mut chars = v.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => Some(c),
+                        _ => None,
+                    }
+                })?;
+                if scalar {
+                    values[0].map(Value::Character)
+                } else {
+                    Some(Value::CharacterArray(values))
+                }
+            }
+            ValueType::String => {
+                if scalar {
+                    Some(Value::String(raw))
+                } else {
+                    Some(Value::StringArray(parse_array(raw, Some)?))
+                }
+            }
+            ValueType::Flag => unreachable!(),
+        };
+
+        Ok(value)
+    }
+}
+
+/// Split a comma-separated list of values, converting each of them. Missing
+/// values (`.`) become `None`; values that cannot be converted are an error.
+fn parse_array<'a, T, F>(raw: &'a str, convert: F) -> Result<Vec<Option<T>>, &'static str>
+where
+    F: Fn(&'a str) -> Option<T>,
+{
+    raw.split(',')
+        .map(|v| match v {
+            "." => Ok(None),
+            v => convert(v)
+                .map(Some)
+                .ok_or("Field has a value of the wrong type"),
+        })
+        .collect()
+}
+
+/// The definition of a FILTER, ALT, contig or SAMPLE, which only require an
+/// identifier
+#[derive(Clone, Debug)]
+pub struct Definition {
+    pub id: String,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl Definition {
+    fn parse(attributes: Vec<(String, String)>) -> Result<Definition, &'static str> {
+        Ok(Definition {
+            id: id(&attributes)?,
+            attributes,
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        attribute(&self.attributes, key)
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.get("Description")
+    }
+
+    /// The length of a contig, if declared
+    pub fn length(&self) -> Option<u64> {
+        self.get("length").and_then(|length| length.parse().ok())
+    }
+}
+
+/// The meta-information lines of a VCF file, with the structured ones parsed
+/// into their definitions
+#[derive(Clone, Debug, Default)]
+pub struct Header {
+    pub fileformat: Option<String>,
+    pub info: Vec<FieldDefinition>,
+    pub format: Vec<FieldDefinition>,
+    pub filters: Vec<Definition>,
+    pub contigs: Vec<Definition>,
+    pub alts: Vec<Definition>,
+    pub samples: Vec<Definition>,
+    /// All other lines, unparsed, including the structured ones that are
+    /// malformed
+    pub other: Vec<(String, String)>,
+    /// The index in the metadata of each malformed structured line, and what
+    /// is wrong with it
+    pub malformed: Vec<(usize, &'static str)>,
+
+    info_index: HashMap<String, usize>,
+    format_index: HashMap<String, usize>,
+    filter_index: HashMap<String, usize>,
+    contig_index: HashMap<String, usize>,
+}
+
+impl Header {
+    /// Parse the metadata read by a `MetadataReader`. Structured lines that
+    /// are malformed are kept in `other` and listed in `malformed`, so that a
+    /// stream can still be read and `validate` can report them.
+    pub fn parse(metadata: &[(String, String)]) -> Header {
+        let mut header = Header::default();
+
+        for (idx, (key, value)) in metadata.iter().enumerate() {
+            if let Err(message) = header.add(key, value) {
+                header.other.push((key.to_string(), value.to_string()));
+                header.malformed.push((idx, message));
+            }
+        }
+
+        header
+    }
+
+    fn add(&mut self, key: &str, value: &str) -> Result<(), &'static str> {
+        let attributes = match key {
+            "INFO" | "FORMAT" | "FILTER" | "contig" | "ALT" | "SAMPLE" => {
+                parse_structured(value).ok_or("Header has a malformed structured line")?
+            }
+            "fileformat" => {
+                self.fileformat = Some(value.to_string());
+                return Ok(());
+            }
+            _ => {
+                self.other.push((key.to_string(), value.to_string()));
+                return Ok(());
+            }
+        };
+
+        match key {
+            "INFO" => {
+                let definition = FieldDefinition::parse(attributes)?;
+                insert(
+                    &mut self.info,
+                    &mut self.info_index,
+                    definition.id.clone(),
+                    definition,
+                );
+            }
+            "FORMAT" => {
+                let definition = FieldDefinition::parse(attributes)?;
+                insert(
+                    &mut self.format,
+                    &mut self.format_index,
+                    definition.id.clone(),
+                    definition,
+                );
+            }
+            "FILTER" => {
+                let definition = Definition::parse(attributes)?;
+                insert(
+                    &mut self.filters,
+                    &mut self.filter_index,
+                    definition.id.clone(),
+                    definition,
+                );
+            }
+            "contig" => {
+                let definition = Definition::parse(attributes)?;
+                insert(
+                    &mut self.contigs,
+                    &mut self.contig_index,
+                    definition.id.clone(),
+                    definition,
+                );
+            }
+            "ALT" => self.alts.push(Definition::parse(attributes)?),
+            _ => self.samples.push(Definition::parse(attributes)?),
+        }
+
+        Ok(())
+    }
+
+    pub fn info(&self, id: &str) -> Option<&FieldDefinition> {
+        self.info_index.get(id).map(|&idx| &self.info[idx])
+    }
+
+    pub fn format(&self, id: &str) -> Option<&FieldDefinition> {
+        self.format_index.get(id).map(|&idx| &self.format[idx])
+    }
+
+    pub fn filter(&self, id: &str) -> Option<&Definition> {
+        self.filter_index.get(id).map(|&idx| &self.filters[idx])
+    }
+
+    pub fn contig(&self, id: &str) -> Option<&Definition> {
+        self.contig_index.get(id).map(|&idx| &self.contigs[idx])
+    }
+}
+
+fn insert<T>(list: &mut Vec<T>, index: &mut HashMap<String, usize>, id: String, item: T) {
+    index.insert(id, list.len());
+    list.push(item);
+}
+
+fn attribute<'a>(attributes: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    attributes.iter().find(|(k, _)| k == key).map(|(_, v)| &**v)
+}
+
+fn id(attributes: &[(String, String)]) -> Result<String, &'static str> {
+    attribute(attributes, "ID")
+        .map(|id| id.to_string())
+        .ok_or("Header definition has no ID")
+}
+
+/// Parse the value of a structured meta-line, such as
+/// `<ID=AF,Number=A,Type=Float,Description="...">`, into its key-value pairs.
+/// Quotes around values are removed.
+pub fn parse_structured(value: &str) -> Option<Vec<(String, String)>> {
+    let inner = value.trim().strip_prefix('<')?.strip_suffix('>')?;
+
+    let mut attributes = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (idx, c) in inner.char_indices().chain(Some((inner.len(), ','))) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                let field = &inner[start..idx];
+                start = idx + 1;
+
+                let mut parts = field.splitn(2, '=');
+                let key = parts.next()?.trim();
+                let value = parts.next()?.trim();
+                let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                    value[1..value.len() - 1].replace("\\\"", "\"")
+                } else {
+                    value.to_string()
+                };
+
+                attributes.push((key.to_string(), value));
+            }
+            _ => {}
+        }
+    }
+
+    if quoted {
+        None
+    } else {
+        Some(attributes)
+    }
+}
@@ -0,0 +1,665 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use vcf::bgzf::BgzfReader;
+use vcf::error::VcfError;
+use vcf::stream::{
+    DataStream, DataStreamFromTextStream, IndividualsReader, IndividualsReaderFromTextStream,
+    MetadataReader, MetadataReaderFromTextStream, VCFColumn, VCFData,
+};
+
+/// The binning scheme used by tabix indexes; CSI indexes declare their own
+const TBI_MIN_SHIFT: u32 = 14;
+const TBI_DEPTH: u32 = 5;
+
+/// A contiguous range of the compressed file, given as virtual offsets
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Chunk {
+    pub begin: u64,
+    pub end: u64,
+}
+
+struct Bin {
+    loffset: u64,
+    chunks: Vec<Chunk>,
+}
+
+struct Reference {
+    bins: HashMap<u32, Bin>,
+    linear: Vec<u64>,
+}
+
+/// A genomic region, with 1-based inclusive coordinates, as in
+/// `chr2:136608646-136608646`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub name: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Region {
+    pub fn new<S: Into<String>>(name: S, start: u64, end: u64) -> Region {
+        Region {
+            name: name.into(),
+            start,
+            end,
+        }
+    }
+
+    /// Whether the closed interval `[start, end]` on the contig `name`
+    /// overlaps this region
+    pub fn overlaps(&self, name: &str, start: u64, end: u64) -> bool {
+        self.name == name && start <= self.end && self.start <= end
+    }
+}
+
+impl FromStr for Region {
+    type Err = &'static str;
+
+    /// Parse `name`, `name:start` or `name:start-end`; a missing end extends
+    /// the region to the end of the contig
+    fn from_str(s: &str) -> Result<Region, &'static str> {
+        let (name, range) = match s.rfind(':') {
+            Some(idx) => (&s[..idx], Some(&s[idx + 1..])),
+            None => (s, None),
+        };
+
+        if name.is_empty() {
+            return Err("Region has no contig name");
+        }
+
+        let parse = |pos: &str| {
+            pos.replace(',', "")
+                .parse::<u64>()
+                .map_err(|_| "Region has an invalid position")
+        };
+
+        let (start, end) = match range {
+            None => (1, u64::MAX),
+            Some(range) => match range.find('-') {
+                None => {
+                    let pos = parse(range)?;
+                    (pos, pos)
+                }
+                Some(idx) if idx + 1 == range.len() => (parse(&range[..idx])?, u64::MAX),
+                Some(idx) => (parse(&range[..idx])?, parse(&range[idx + 1..])?),
+            },
+        };
+
+        if start == 0 || start > end {
+            return Err("Region has an invalid range");
+        }
+
+        Ok(Region::new(name, start, end))
+    }
+}
+
+/// A tabix (`.tbi`) or CSI (`.csi`) index of a BGZF compressed VCF file
+pub struct Index {
+    min_shift: u32,
+    depth: u32,
+    names: HashMap<String, usize>,
+    references: Vec<Reference>,
+}
+
+impl Index {
+    /// Read an index from its (BGZF compressed) contents. The format is
+    /// detected from the magic bytes.
+    pub fn read<R: Read>(reader: R) -> io::Result<Index> {
+        let mut reader = BgzfReader::new(reader);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+
+        match &magic {
+            b"TBI\x01" => Index::read_tbi(&mut reader),
+            b"CSI\x01" => Index::read_csi(&mut reader),
+            _ => Err(invalid_data("Not a tabix or CSI index")),
+        }
+    }
+
+    /// Read the index at the given path
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Index> {
+        Index::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Find the index of the given VCF file, looking for `<path>.tbi` and then
+    /// `<path>.csi`
+    pub fn for_vcf<P: AsRef<Path>>(path: P) -> io::Result<Index> {
+        for extension in &["tbi", "csi"] {
+            let mut index_path = PathBuf::from(path.as_ref());
+            let mut file_name = index_path.file_name().unwrap_or_default().to_owned();
+            file_name.push(".");
+            file_name.push(extension);
+            index_path.set_file_name(file_name);
+
+            if index_path.is_file() {
+                return Index::from_path(index_path);
+            }
+        }
+
+        let msg = format!("No index found for {:?}", path.as_ref());
+        Err(Error::new(ErrorKind::NotFound, msg))
+    }
+
+    fn read_tbi<R: Read>(reader: &mut R) -> io::Result<Index> {
+        let n_ref = read_len(reader)?;
+        let names = read_tabix_header(reader)?;
+
+        let mut references = Vec::with_capacity(n_ref);
+        for _ in 0..n_ref {
+            let mut bins = HashMap::new();
+            for _ in 0..read_len(reader)? {
+                let bin = read_u32(reader)?;
+                let chunks = read_chunks(reader)?;
+                bins.insert(bin, Bin { loffset: 0, chunks });
+            }
+
+            let mut linear = Vec::new();
+            for _ in 0..read_len(reader)? {
+                linear.push(read_u64(reader)?);
+            }
+
+            references.push(Reference { bins, linear });
+        }
+
+        Index::new(TBI_MIN_SHIFT, TBI_DEPTH, names, references)
+    }
+
+    fn read_csi<R: Read>(reader: &mut R) -> io::Result<Index> {
+        let min_shift = read_u32(reader)?;
+        let depth = read_u32(reader)?;
+
+        let mut aux = vec![0u8; read_len(reader)?];
+        reader.read_exact(&mut aux)?;
+
+        // The auxiliary data of a CSI index over a VCF holds the same header
+        // as a tabix index
+        let names = if aux.is_empty() {
+            Vec::new()
+        } else {
+            read_tabix_header(&mut &aux[..])?
+        };
+
+        let n_ref = read_len(reader)?;
+        let mut references = Vec::with_capacity(n_ref);
+        for _ in 0..n_ref {
+            let mut bins = HashMap::new();
+            for _ in 0..read_len(reader)? {
+                let bin = read_u32(reader)?;
+                let loffset = read_u64(reader)?;
+                let chunks = read_chunks(reader)?;
+                bins.insert(bin, Bin { loffset, chunks });
+            }
+
+            references.push(Reference {
+                bins,
+                linear: Vec::new(),
+            });
+        }
+
+        Index::new(min_shift, depth, names, references)
+    }
+
+    fn new(
+        min_shift: u32,
+        depth: u32,
+        names: Vec<String>,
+        references: Vec<Reference>,
+    ) -> io::Result<Index> {
+        if names.len() != references.len() {
+            return Err(invalid_data(
+                "Index has a different number of names and references",
+            ));
+        }
+
+        Ok(Index {
+            min_shift,
+            depth,
+            names: names.into_iter().enumerate().map(|(i, n)| (n, i)).collect(),
+            references,
+        })
+    }
+
+    /// The sorted, non-overlapping chunks of the file that may contain records
+    /// overlapping the given region
+    pub fn chunks(&self, region: &Region) -> Vec<Chunk> {
+        let reference = match self.names.get(&region.name) {
+            Some(&tid) => &self.references[tid],
+            None => return Vec::new(),
+        };
+
+        // Bins work on 0-based, half-open coordinates
+        let max_pos = 1u64 << (self.min_shift + 3 * self.depth);
+        let begin = (region.start - 1).min(max_pos - 1);
+        let end = region.end.min(max_pos);
+
+        let min_offset = self.min_offset(reference, begin);
+
+        let mut chunks = self
+            .bins(begin, end)
+            .iter()
+            .filter_map(|bin| reference.bins.get(bin))
+            .flat_map(|bin| bin.chunks.iter())
+            .filter(|chunk| chunk.end > min_offset)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        chunks.sort();
+
+        let mut merged: Vec<Chunk> = Vec::with_capacity(chunks.len());
+        for chunk in chunks {
+            match merged.last_mut() {
+                Some(last) if chunk.begin <= last.end => last.end = last.end.max(chunk.end),
+                _ => merged.push(chunk),
+            }
+        }
+
+        merged
+    }
+
+    /// The bins overlapping the 0-based, half-open interval `[begin, end)`
+    fn bins(&self, begin: u64, end: u64) -> Vec<u32> {
+        let mut bins = Vec::new();
+        let end = end - 1;
+        let mut shift = self.min_shift + 3 * self.depth;
+        let mut offset = 0u64;
+
+        for level in 0..=self.depth {
+            let first = offset + (begin >> shift);
+            let last = offset + (end >> shift);
+            bins.extend((first..=last).map(|bin| bin as u32));
+
+            shift -= 3;
+            offset += 1 << (3 * level);
+        }
+
+        bins
+    }
+
+    /// The smallest virtual offset at which a record overlapping `begin` can
+    /// start, used to skip chunks that end before it
+    fn min_offset(&self, reference: &Reference, begin: u64) -> u64 {
+        if !reference.linear.is_empty() {
+            let idx = (begin >> self.min_shift) as usize;
+            return reference.linear[idx.min(reference.linear.len() - 1)];
+        }
+
+        // CSI indexes store the offset in each bin instead; use the one of the
+        // smallest existing bin containing `begin`
+        let mut shift = self.min_shift;
+        for level in (0..=self.depth).rev() {
+            let offset = ((1u64 << (3 * level)) - 1) / 7;
+            let bin = (offset + (begin >> shift)) as u32;
+            if let Some(bin) = reference.bins.get(&bin) {
+                return bin.loffset;
+            }
+            shift += 3;
+        }
+
+        0
+    }
+}
+
+/// Create a stream over the records of the VCF file at the given path that
+/// overlap any of the given regions, such as `2:136608646-136608646`. The file
+/// must be BGZF compressed and have a `.tbi` or `.csi` index next to it.
+pub fn query<P, I, Rstr>(path: P, regions: I) -> io::Result<impl MetadataReader>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = Rstr>,
+    Rstr: AsRef<str>,
+{
+    let index = Index::for_vcf(&path)?;
+    let regions = regions
+        .into_iter()
+        .map(|r| r.as_ref().parse())
+        .collect::<Result<Vec<Region>, _>>()
+        .map_err(|msg| Error::new(ErrorKind::InvalidInput, msg))?;
+
+    let file = BufReader::new(File::open(path)?);
+    Ok(from_regions(file, &index, regions))
+}
+
+/// Create a stream over the records of a BGZF compressed VCF that overlap any
+/// of the given regions. Regions are visited in the given order, so records
+/// overlapping more than one region are produced more than once.
+pub fn from_regions<R, I>(reader: R, index: &Index, regions: I) -> impl MetadataReader
+where
+    R: Read + Seek,
+    I: IntoIterator<Item = Region>,
+{
+    let queries = regions
+        .into_iter()
+        .map(|region| {
+            let chunks = index.chunks(&region);
+            (region, chunks)
+        })
+        .collect::<Vec<_>>();
+
+    MetadataReaderFromRegions {
+        inner: MetadataReaderFromTextStream::new(BgzfReader::new(reader)),
+        queries,
+    }
+}
+
+struct MetadataReaderFromRegions<R: Read + Seek> {
+    inner: MetadataReaderFromTextStream<BgzfReader<R>>,
+    queries: Vec<(Region, Vec<Chunk>)>,
+}
+
+impl<R: Read + Seek> MetadataReader for MetadataReaderFromRegions<R> {
+    type Next = IndividualsReaderFromRegions<R>;
+
+    fn read_metadata(
+        self,
+        metadata: &mut Vec<(String, String)>,
+    ) -> Result<Self::Next, VcfError> {
+        Ok(IndividualsReaderFromRegions {
+            inner: self.inner.read_metadata(metadata)?,
+            queries: self.queries,
+        })
+    }
+}
+
+struct IndividualsReaderFromRegions<R: Read + Seek> {
+    inner: IndividualsReaderFromTextStream<BgzfReader<R>>,
+    queries: Vec<(Region, Vec<Chunk>)>,
+}
+
+impl<R: Read + Seek> IndividualsReader for IndividualsReaderFromRegions<R> {
+    type Next = DataStreamFromRegions<R>;
+
+    fn read_individuals(self, list: &mut Vec<String>) -> Result<Self::Next, VcfError> {
+        let stream: DataStreamFromTextStream<_> = self.inner.read_individuals(list)?;
+        let (reader, genotype, columns) = stream.into_inner();
+
+        let mut queries = self.queries;
+        queries.reverse();
+
+        Ok(DataStreamFromRegions {
+            reader,
+            genotype,
+            columns,
+            queries,
+            region: None,
+            chunks: Vec::new(),
+            chunk_end: 0,
+            failed: false,
+        })
+    }
+}
+
+struct DataStreamFromRegions<R: Read + Seek> {
+    reader: BgzfReader<R>,
+    genotype: bool,
+    columns: usize,
+
+    // Both the pending queries and the pending chunks of the current query are
+    // kept in reverse order, so that the next one can be popped
+    queries: Vec<(Region, Vec<Chunk>)>,
+    region: Option<Region>,
+    chunks: Vec<Chunk>,
+    chunk_end: u64,
+    failed: bool,
+}
+
+impl<R: Read + Seek> DataStreamFromRegions<R> {
+    /// Seek to the start of the next chunk to read, returning `false` if there
+    /// is none left
+    fn advance(&mut self) -> io::Result<bool> {
+        loop {
+            if let Some(chunk) = self.chunks.pop() {
+                self.reader.seek_virtual(chunk.begin)?;
+                self.chunk_end = chunk.end;
+                return Ok(true);
+            }
+
+            match self.queries.pop() {
+                Some((region, mut chunks)) => {
+                    chunks.reverse();
+                    self.region = Some(region);
+                    self.chunks = chunks;
+                }
+                None => return Ok(false),
+            }
+        }
+    }
+
+    /// Read the next record overlapping the current region
+    fn read_next(&mut self) -> Result<Option<VCFData>, VcfError> {
+        loop {
+            if (self.region.is_none() || self.reader.virtual_offset() >= self.chunk_end)
+                && !self.advance()?
+            {
+                return Ok(None);
+            }
+
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+
+            let data = VCFData::parse(line, self.genotype, self.columns, None)?;
+            let region = match self.region {
+                Some(ref region) => region,
+                None => return Ok(None),
+            };
+
+            let start = match data.position().parse::<u64>() {
+                Ok(start) => start,
+                Err(_) => {
+                    let message = "Position is not a number";
+                    let column = Some(VCFColumn::POS as usize + 1);
+                    return Err(VcfError::syntax(None, column, data.position(), message));
+                }
+            };
+
+            if data.chromosome() == region.name && start > region.end {
+                // Records are sorted, so nothing else in this region can
+                // overlap it; move on to the next one
+                self.chunks.clear();
+                self.chunk_end = 0;
+                continue;
+            }
+
+            if region.overlaps(data.chromosome(), start, record_end(&data, start)) {
+                return Ok(Some(data));
+            }
+        }
+    }
+}
+
+impl<R: Read + Seek> Iterator for DataStreamFromRegions<R> {
+    type Item = Result<VCFData, VcfError>;
+
+    fn next(&mut self) -> Option<Result<VCFData, VcfError>> {
+        if self.failed {
+            return None;
+        }
+
+        let result = self.read_next();
+        // A malformed record does not affect the ones after it
+        self.failed = matches!(result, Err(VcfError::Io { .. }));
+        result.transpose()
+    }
+}
+
+impl<R: Read + Seek> DataStream for DataStreamFromRegions<R> {}
+
+/// The last position covered by a record: the one given by the `END` INFO
+/// field if present, otherwise the one spanned by the reference allele
+pub(crate) fn record_end(data: &VCFData, start: u64) -> u64 {
+    let info_end = data
+        .info_raw("END")
+        .flatten()
+        .and_then(|end| end.parse().ok());
+
+    info_end.unwrap_or(start + data.reference().len().max(1) as u64 - 1)
+}
+
+/// Read the tabix header, returning the names of the references
+fn read_tabix_header<R: Read>(reader: &mut R) -> io::Result<Vec<String>> {
+    // Format, column of the sequence name, start and end, the meta character
+    // and number of skipped lines, which are fixed for VCF
+    for _ in 0..6 {
+        read_u32(reader)?;
+    }
+
+    let mut names = vec![0u8; read_len(reader)?];
+    reader.read_exact(&mut names)?;
+
+    names
+        .split(|&b| b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            String::from_utf8(name.to_vec()).map_err(|_| invalid_data("Invalid reference name"))
+        })
+        .collect()
+}
+
+fn read_chunks<R: Read>(reader: &mut R) -> io::Result<Vec<Chunk>> {
+    let n_chunk = read_len(reader)?;
+    let mut chunks = Vec::with_capacity(n_chunk);
+
+    for _ in 0..n_chunk {
+        let begin = read_u64(reader)?;
+        let end = read_u64(reader)?;
+        chunks.push(Chunk { begin, end });
+    }
+
+    Ok(chunks)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Read a signed 32-bit count, rejecting negative values
+fn read_len<R: Read>(reader: &mut R) -> io::Result<usize> {
+    let value = read_u32(reader)? as i32;
+    if value < 0 {
+        Err(invalid_data("Index contains a negative count"))
+    } else {
+        Ok(value as usize)
+    }
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::DeflateEncoder;
+    use flate2::{Compression, Crc};
+    use std::io::{Cursor, Write};
+
+    /// Compress some bytes into a single BGZF block
+    fn block(data: &[u8]) -> Vec<u8> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        let cdata = encoder.finish().unwrap();
+
+        let mut crc = Crc::new();
+        crc.update(data);
+
+        let size = (18 + cdata.len() + 8 - 1) as u16;
+        let mut block = vec![31, 139, 8, 4, 0, 0, 0, 0, 0, 255, 6, 0, b'B', b'C', 2, 0];
+        block.extend_from_slice(&size.to_le_bytes());
+        block.extend_from_slice(&cdata);
+        block.extend_from_slice(&crc.sum().to_le_bytes());
+        block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        block
+    }
+
+    /// A tabix index of a single contig `1`, with one chunk in the bin of its
+    /// first 16 kb
+    fn tbi(chunk: Chunk, linear: u64) -> Vec<u8> {
+        let mut index = b"TBI\x01".to_vec();
+        for value in &[1u32, 2, 1, 2, 0, b'#' as u32, 0, 2] {
+            index.extend_from_slice(&value.to_le_bytes());
+        }
+        index.extend_from_slice(b"1\0");
+        for value in &[1u32, 4681, 1] {
+            index.extend_from_slice(&value.to_le_bytes());
+        }
+        index.extend_from_slice(&chunk.begin.to_le_bytes());
+        index.extend_from_slice(&chunk.end.to_le_bytes());
+        index.extend_from_slice(&1u32.to_le_bytes());
+        index.extend_from_slice(&linear.to_le_bytes());
+
+        let mut bytes = block(&index);
+        bytes.extend(block(b""));
+        bytes
+    }
+
+    #[test]
+    fn bins_of_tabix_indexes() {
+        let index = Index::new(TBI_MIN_SHIFT, TBI_DEPTH, Vec::new(), Vec::new()).unwrap();
+
+        assert_eq!(index.bins(0, 1), vec![0, 1, 9, 73, 585, 4681]);
+        assert_eq!(
+            index.bins(16_383, 16_385),
+            vec![0, 1, 9, 73, 585, 4681, 4682]
+        );
+        assert_eq!(index.bins(131_072, 131_073), vec![0, 1, 9, 73, 586, 4689]);
+        assert_eq!(
+            index.bins(1 << 26, (1 << 26) + 1),
+            vec![0, 2, 17, 137, 1097, 8777]
+        );
+    }
+
+    #[test]
+    fn query_reads_a_chunk_across_blocks() {
+        let header = "##fileformat=VCFv4.2\n\
+                      ##contig=<ID=1>\n\
+                      #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tA\n";
+        let first = "1\t100\trs100\tA\tG\t.\tPASS\t.\tGT\t0/1\n\
+                     1\t200\trs200\tA\tG\t.\tPASS\t.\tGT\t1/1\n";
+        let second = "1\t300\trs300\tA\tG\t.\tPASS\t.\tGT\t0/0\n\
+                      1\t400\trs400\tA\tG\t.\tPASS\t.\tGT\t0/1\n";
+
+        let mut file = block(header.as_bytes());
+        let first_offset = file.len() as u64;
+        file.extend(block(first.as_bytes()));
+        let second_offset = file.len() as u64;
+        file.extend(block(second.as_bytes()));
+        file.extend(block(b""));
+
+        // One chunk from the first record to the end of the last, which
+        // starts in one block and ends in the other
+        let chunk = Chunk {
+            begin: first_offset << 16,
+            end: (second_offset << 16) | second.len() as u64,
+        };
+        let index = Index::read(Cursor::new(tbi(chunk, first_offset << 16))).unwrap();
+        assert_eq!(index.chunks(&Region::new("1", 150, 350)), vec![chunk]);
+        assert_eq!(index.chunks(&Region::new("2", 1, 1000)), vec![]);
+
+        let regions = vec![Region::new("1", 150, 350), Region::new("1", 400, 400)];
+        let unfolded = from_regions(Cursor::new(file), &index, regions)
+            .unfold()
+            .unwrap();
+        assert_eq!(unfolded.individuals, vec!["A"]);
+
+        let identifiers = unfolded
+            .stream
+            .map(|data| data.unwrap().identifier().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(identifiers, vec!["rs200", "rs300", "rs400"]);
+    }
+}
@@ -0,0 +1,509 @@
+use vcf::error::VcfError;
+use vcf::genotype::GenotypeArray;
+use vcf::stream::{DataStream, IndividualsReader, MetadataReader, VCFColumn, VCFData};
+
+/// The code of a missing call in the packed matrix
+const MISSING: u8 = 3;
+
+/// The number of calls packed in each byte
+pub(crate) const CALLS_PER_BYTE: usize = 4;
+
+/// The number of haploid flags packed in each byte
+pub(crate) const FLAGS_PER_BYTE: usize = 8;
+
+/// The fixed columns of a record that identify a variant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variant {
+    pub chromosome: String,
+    pub position: u64,
+    pub identifier: String,
+    pub reference: String,
+    pub alternatives: Vec<String>,
+}
+
+impl Variant {
+    pub fn from_data<L: AsRef<str>>(data: &VCFData<L>) -> Result<Variant, VcfError> {
+        let position = data.position().parse().map_err(|_| {
+            let column = Some(VCFColumn::POS as usize + 1);
+            VcfError::syntax(None, column, data.position(), "POS is not a number")
+        })?;
+
+        Ok(Variant {
+            chromosome: data.chromosome().to_string(),
+            position,
+            identifier: data.identifier().to_string(),
+            reference: data.reference().to_string(),
+            alternatives: match data.alternative_count() {
+                0 => Vec::new(),
+                _ => data.alternatives().iter().map(|a| a.to_string()).collect(),
+            },
+        })
+    }
+}
+
+/// The genotype calls of a set of individuals at a set of variants, read once
+/// and kept in memory.
+///
+/// Each call is the number of copies of alternate alleles, between 0 and 2,
+/// taken from GT, or from DS rounded to an integer where GT is missing.
+/// Calls are packed in 2 bits each, one row of bytes per variant, so that a
+/// whole chromosome of the 1000 Genomes individuals fits in memory. Calls
+/// with missing alleles or with more than two alternate alleles are missing.
+/// Haploid calls, such as those of males on chrX, are flagged in a second
+/// matrix of 1 bit per call; all other calls count as diploid.
+///
+/// The matrix is also a `MetadataReader`, whose records have the calls in DS,
+/// so that it can take the place of the VCF it was read from. The records
+/// also carry decoded calls, which keep the ploidy of each call.
+#[derive(Debug, Clone, Default)]
+pub struct GenotypeMatrix {
+    individuals: Vec<String>,
+    variants: Vec<Variant>,
+    calls: Vec<u8>,
+    haploid: Vec<u8>,
+}
+
+impl GenotypeMatrix {
+    /// An empty matrix with the given individuals, to which variants can be
+    /// pushed
+    pub fn new(individuals: Vec<String>) -> GenotypeMatrix {
+        GenotypeMatrix {
+            individuals,
+            variants: Vec::new(),
+            calls: Vec::new(),
+            haploid: Vec::new(),
+        }
+    }
+
+    /// Read all the records of a stream
+    pub fn load<M: MetadataReader>(stream: M) -> Result<GenotypeMatrix, VcfError> {
+        let unfolded = stream.unfold()?;
+        GenotypeMatrix::from_stream(unfolded.individuals, unfolded.stream)
+    }
+
+    /// Read all the records of a data stream whose samples are the given
+    /// individuals
+    pub fn from_stream<S: DataStream>(
+        individuals: Vec<String>,
+        mut stream: S,
+    ) -> Result<GenotypeMatrix, VcfError> {
+        let mut matrix = GenotypeMatrix::new(individuals);
+
+        let mut data = VCFData::default();
+        while let Some(result) = stream.read_into(&mut data) {
+            result?;
+            matrix.push_data(&data)?;
+        }
+
+        Ok(matrix)
+    }
+
+    /// Add the variant and the calls of a record, which must have a sample
+    /// for each individual of the matrix
+    pub fn push_data<L: AsRef<str>>(&mut self, data: &VCFData<L>) -> Result<(), VcfError> {
+        let variant = Variant::from_data(data)?;
+
+        let gt = data.format_index("GT");
+        let ds = data.format_index("DS");
+
+        // Fields are looked up directly, since parsing a `Genotype` for each
+        // of thousands of samples would allocate for each, unless the reader
+        // has decoded them already
+        let decoded = data.decoded_calls();
+        let calls = data.genotypes().enumerate().map(|(idx, sample)| {
+            let field = |position: Option<usize>| {
+                position
+                    .and_then(|p| sample.split(':').nth(p))
+                    .filter(|&v| v != ".")
+            };
+            let (called, ploidy) = match decoded {
+                Some(decoded) => {
+                    let biallelic = decoded.max_allele(idx) <= Some(1);
+                    let dosage = decoded.dosage(idx).filter(|_| biallelic);
+                    (dosage, decoded.ploidy(idx))
+                }
+                None => match field(gt) {
+                    Some(gt) => (gt_dosage(gt), gt_ploidy(gt)),
+                    None => (None, 2),
+                },
+            };
+            let dosage = match called {
+                Some(dosage) => Some(dosage as f32),
+                None => field(ds).and_then(|ds| ds.parse::<f32>().ok()),
+            };
+            let call = dosage
+                .map(f32::round)
+                .filter(|dosage| (0.0..=2.0).contains(dosage))
+                .map(|dosage| dosage as u8);
+
+            (call, ploidy)
+        });
+        self.push_calls(variant, calls);
+
+        Ok(())
+    }
+
+    /// Add a variant with one diploid call per individual; missing calls at
+    /// the end may be left out
+    pub fn push<I>(&mut self, variant: Variant, calls: I)
+    where
+        I: IntoIterator<Item = Option<u8>>,
+    {
+        self.push_calls(variant, calls.into_iter().map(|call| (call, 2)));
+    }
+
+    /// Add a variant with the call and the ploidy of each individual; missing
+    /// calls at the end may be left out
+    pub fn push_calls<I>(&mut self, variant: Variant, calls: I)
+    where
+        I: IntoIterator<Item = (Option<u8>, usize)>,
+    {
+        let start = self.calls.len();
+        self.calls.resize(start + self.stride(), 0xFF);
+        let flags_start = self.haploid.len();
+        self.haploid.resize(flags_start + self.flag_stride(), 0);
+
+        let row = &mut self.calls[start..];
+        let flags = &mut self.haploid[flags_start..];
+        for (idx, (call, ploidy)) in calls.into_iter().take(self.individuals.len()).enumerate() {
+            set(row, idx, call);
+            if ploidy == 1 {
+                flags[idx / FLAGS_PER_BYTE] |= 1 << (idx % FLAGS_PER_BYTE);
+            }
+        }
+
+        self.variants.push(variant);
+    }
+
+    pub fn individuals(&self) -> &[String] {
+        &self.individuals
+    }
+
+    pub fn variants(&self) -> &[Variant] {
+        &self.variants
+    }
+
+    pub fn individual_count(&self) -> usize {
+        self.individuals.len()
+    }
+
+    pub fn variant_count(&self) -> usize {
+        self.variants.len()
+    }
+
+    /// The call of an individual at a variant
+    pub fn get(&self, variant: usize, individual: usize) -> Option<u8> {
+        assert!(individual < self.individuals.len());
+        get(self.packed_row(variant), individual)
+    }
+
+    /// The ploidy of the call of an individual at a variant, 1 for haploid
+    /// calls and 2 for all others
+    pub fn ploidy(&self, variant: usize, individual: usize) -> usize {
+        assert!(individual < self.individuals.len());
+        ploidy(self.packed_flags(variant), individual)
+    }
+
+    /// The calls of all the individuals at a variant
+    pub fn row(&self, variant: usize) -> Row<'_> {
+        Row {
+            row: self.packed_row(variant),
+            index: 0,
+            count: self.individuals.len(),
+        }
+    }
+
+    /// The calls of all the individuals at each variant, along with the
+    /// variant
+    pub fn rows(&self) -> impl Iterator<Item = (&Variant, Row<'_>)> {
+        let rows = (0..self.variants.len()).map(move |idx| self.row(idx));
+        self.variants.iter().zip(rows)
+    }
+
+    /// The calls of an individual at all the variants
+    pub fn column(&self, individual: usize) -> impl Iterator<Item = Option<u8>> + '_ {
+        assert!(individual < self.individuals.len());
+        (0..self.variants.len()).map(move |idx| get(self.packed_row(idx), individual))
+    }
+
+    /// A matrix with only the individuals at the given indices, in the given
+    /// order
+    pub fn subset(&self, indices: &[usize]) -> GenotypeMatrix {
+        let individuals = indices
+            .iter()
+            .map(|&idx| self.individuals[idx].clone())
+            .collect();
+
+        let mut matrix = GenotypeMatrix::new(individuals);
+        for (idx, variant) in self.variants.iter().enumerate() {
+            let row = self.packed_row(idx);
+            let flags = self.packed_flags(idx);
+            let calls = indices
+                .iter()
+                .map(|&individual| (get(row, individual), ploidy(flags, individual)));
+            matrix.push_calls(variant.clone(), calls);
+        }
+
+        matrix
+    }
+
+    /// Build a matrix from its packed calls and haploid flags, which must
+    /// have one row for each variant
+    pub(crate) fn from_packed(
+        individuals: Vec<String>,
+        variants: Vec<Variant>,
+        calls: Vec<u8>,
+        haploid: Vec<u8>,
+    ) -> GenotypeMatrix {
+        let matrix = GenotypeMatrix {
+            individuals,
+            variants,
+            calls,
+            haploid,
+        };
+        assert_eq!(matrix.calls.len(), matrix.stride() * matrix.variants.len());
+        assert_eq!(
+            matrix.haploid.len(),
+            matrix.flag_stride() * matrix.variants.len()
+        );
+        matrix
+    }
+
+    /// The packed calls of all the variants, one row after the other
+    pub(crate) fn packed(&self) -> &[u8] {
+        &self.calls
+    }
+
+    /// The packed haploid flags of all the variants, one row after the other
+    pub(crate) fn packed_haploid(&self) -> &[u8] {
+        &self.haploid
+    }
+
+    /// The number of bytes in a row
+    pub(crate) fn stride(&self) -> usize {
+        self.individuals.len().div_ceil(CALLS_PER_BYTE)
+    }
+
+    /// The number of bytes in a row of haploid flags
+    pub(crate) fn flag_stride(&self) -> usize {
+        self.individuals.len().div_ceil(FLAGS_PER_BYTE)
+    }
+
+    pub(crate) fn packed_row(&self, variant: usize) -> &[u8] {
+        let stride = self.stride();
+        &self.calls[variant * stride..(variant + 1) * stride]
+    }
+
+    fn packed_flags(&self, variant: usize) -> &[u8] {
+        let stride = self.flag_stride();
+        &self.haploid[variant * stride..(variant + 1) * stride]
+    }
+}
+
+/// The calls of all the individuals at a variant
+pub struct Row<'a> {
+    row: &'a [u8],
+    index: usize,
+    count: usize,
+}
+
+impl<'a> Iterator for Row<'a> {
+    type Item = Option<u8>;
+
+    fn next(&mut self) -> Option<Option<u8>> {
+        if self.index >= self.count {
+            return None;
+        }
+        let call = get(self.row, self.index);
+        self.index += 1;
+        Some(call)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.count - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for Row<'a> {}
+
+impl MetadataReader for GenotypeMatrix {
+    type Next = IndividualsReaderFromMatrix;
+
+    fn read_metadata(self, metadata: &mut Vec<(String, String)>) -> Result<Self::Next, VcfError> {
+        metadata.push(("fileformat".to_string(), "VCFv4.2".to_string()));
+        metadata.push((
+            "FORMAT".to_string(),
+            "<ID=DS,Number=1,Type=Float,Description=\"Number of alternate alleles\">".to_string(),
+        ));
+
+        Ok(IndividualsReaderFromMatrix { matrix: self })
+    }
+}
+
+pub struct IndividualsReaderFromMatrix {
+    matrix: GenotypeMatrix,
+}
+
+impl IndividualsReader for IndividualsReaderFromMatrix {
+    type Next = DataStreamFromMatrix;
+
+    fn read_individuals(self, list: &mut Vec<String>) -> Result<Self::Next, VcfError> {
+        list.extend(self.matrix.individuals.iter().cloned());
+
+        Ok(DataStreamFromMatrix {
+            matrix: self.matrix,
+            variant: 0,
+        })
+    }
+}
+
+pub struct DataStreamFromMatrix {
+    matrix: GenotypeMatrix,
+    variant: usize,
+}
+
+impl Iterator for DataStreamFromMatrix {
+    type Item = Result<VCFData, VcfError>;
+
+    fn next(&mut self) -> Option<Result<VCFData, VcfError>> {
+        let variant = self.matrix.variants.get(self.variant)?;
+
+        let alternatives = match variant.alternatives.len() {
+            0 => ".".to_string(),
+            _ => variant.alternatives.join(","),
+        };
+        let fixed = vec![
+            variant.chromosome.clone(),
+            variant.position.to_string(),
+            variant.identifier.clone(),
+            variant.reference.clone(),
+            alternatives,
+            ".".to_string(),
+            ".".to_string(),
+            ".".to_string(),
+            "DS".to_string(),
+        ];
+        let calls = self.matrix.row(self.variant).map(|call| match call {
+            Some(call) => call.to_string(),
+            None => ".".to_string(),
+        });
+
+        // DS alone cannot tell a haploid call from a diploid one, so the
+        // calls are also given decoded, with their ploidy
+        let flags = self.matrix.packed_flags(self.variant);
+        let mut values = Vec::with_capacity(2 * self.matrix.individual_count());
+        for (idx, call) in self.matrix.row(self.variant).enumerate() {
+            let ploidy = ploidy(flags, idx);
+            for allele in 0..2 {
+                values.push(match call {
+                    _ if allele >= ploidy => GenotypeArray::END,
+                    // Alleles are encoded as in BCF, unphased
+                    Some(dosage) if (allele as u8) < dosage => 4,
+                    Some(_) => 2,
+                    None => 0,
+                });
+            }
+        }
+
+        self.variant += 1;
+
+        let data = VCFData::from_fields(fixed.into_iter().chain(calls));
+        Some(Ok(data.with_calls(GenotypeArray::new(2, values))))
+    }
+}
+
+impl DataStream for DataStreamFromMatrix {}
+
+/// The number of alternate alleles in a GT value, or `None` if any allele is
+/// missing or malformed, or is a second alternate allele that the matrix
+/// cannot tell apart from the first
+fn gt_dosage(gt: &str) -> Option<usize> {
+    // Scanning the bytes is several times faster than splitting the string
+    // and parsing each allele. Allele indexes are capped at 2, which is enough
+    // to reject them.
+    let mut dosage = 0;
+    let mut allele: Option<usize> = None;
+    for &byte in gt.as_bytes() {
+        match byte {
+            b'0'..=b'9' => {
+                let digit = (byte - b'0') as usize;
+                allele = Some((allele.unwrap_or(0) * 10 + digit).min(2));
+            }
+            b'/' | b'|' => {
+                dosage += biallelic(allele?)?;
+                allele = None;
+            }
+            _ => return None,
+        }
+    }
+    Some(dosage + biallelic(allele?)?)
+}
+
+fn biallelic(allele: usize) -> Option<usize> {
+    match allele {
+        0 | 1 => Some(allele),
+        _ => None,
+    }
+}
+
+/// The number of alleles in a GT value
+fn gt_ploidy(gt: &str) -> usize {
+    1 + gt.bytes().filter(|&b| b == b'/' || b == b'|').count()
+}
+
+/// The ploidy of a call, from its haploid flag
+fn ploidy(flags: &[u8], index: usize) -> usize {
+    match (flags[index / FLAGS_PER_BYTE] >> (index % FLAGS_PER_BYTE)) & 1 {
+        1 => 1,
+        _ => 2,
+    }
+}
+
+fn get(row: &[u8], index: usize) -> Option<u8> {
+    let shift = 2 * (index % CALLS_PER_BYTE);
+    match (row[index / CALLS_PER_BYTE] >> shift) & 0b11 {
+        MISSING => None,
+        call => Some(call),
+    }
+}
+
+fn set(row: &mut [u8], index: usize, call: Option<u8>) {
+    let code = match call {
+        Some(call) if call < MISSING => call,
+        _ => MISSING,
+    };
+    let shift = 2 * (index % CALLS_PER_BYTE);
+    let byte = &mut row[index / CALLS_PER_BYTE];
+    *byte = (*byte & !(0b11 << shift)) | (code << shift);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gt_dosages() {
+        assert_eq!(gt_dosage("0/1"), Some(1));
+        assert_eq!(gt_dosage("1|1"), Some(2));
+        assert_eq!(gt_dosage("0|0"), Some(0));
+        assert_eq!(gt_dosage("./."), None);
+        assert_eq!(gt_dosage("1"), Some(1));
+        assert_eq!(gt_dosage("1/2"), None);
+        assert_eq!(gt_dosage("0/10"), None);
+    }
+
+    #[test]
+    fn decoded_calls_of_a_second_alternate_allele_are_missing() {
+        let fields = "1\t100\t.\tA\tG,T\t.\tPASS\t.\tGT\t0/1\t1/2\t0/0".split('\t');
+        // 0/1, 1/2 and 0/0 in the encoding of BCF
+        let calls = GenotypeArray::new(2, vec![2, 4, 4, 6, 2, 2]);
+        let data = VCFData::from_fields(fields).with_calls(calls);
+
+        let mut matrix = GenotypeMatrix::new(vec!["A".into(), "B".into(), "C".into()]);
+        matrix.push_data(&data).unwrap();
+        assert_eq!(matrix.column(0).collect::<Vec<_>>(), vec![Some(1)]);
+        assert_eq!(matrix.column(1).collect::<Vec<_>>(), vec![None]);
+        assert_eq!(matrix.column(2).collect::<Vec<_>>(), vec![Some(0)]);
+    }
+}
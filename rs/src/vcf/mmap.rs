@@ -0,0 +1,285 @@
+use std::fs::File;
+use std::ops::Range;
+use std::path::Path;
+use std::str;
+use std::thread;
+
+use memchr::memchr;
+use memmap2::Mmap;
+
+use vcf::bgzf::is_gzip;
+use vcf::error::VcfError;
+use vcf::stream::{
+    DataStream, IndividualsReader, MetadataReader, MetadataReaderFromTextStream, VCFData,
+};
+
+/// An uncompressed VCF file mapped into memory.
+///
+/// Records borrow their lines from the mapping instead of copying them. They
+/// can be read starting at the byte offset of any data line, which allows
+/// random access and scanning several parts of the file in parallel. A
+/// reference to the file is also a `MetadataReader`, whose records are copied
+/// out of the mapping.
+pub struct MappedVcf {
+    map: Mmap,
+    metadata: Vec<(String, String)>,
+    individuals: Vec<String>,
+    genotype: bool,
+    columns: usize,
+
+    // The offset and the line number of the first data line
+    data_start: usize,
+    first_line: usize,
+}
+
+impl MappedVcf {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MappedVcf, VcfError> {
+        let file = File::open(path)?;
+
+        // The mapping is only sound as long as the file is not modified while
+        // mapped, which we have to trust other processes not to do
+        let map = unsafe { Mmap::map(&file)? };
+        if is_gzip(&map) {
+            return Err("Only uncompressed VCF files can be memory-mapped".into());
+        }
+
+        let mut metadata = Vec::new();
+        let mut individuals = Vec::new();
+        let (rest, genotype, columns) = MetadataReaderFromTextStream::new(&map[..])
+            .read_metadata(&mut metadata)?
+            .read_individuals(&mut individuals)?
+            .into_inner();
+        let data_start = map.len() - rest.len();
+
+        // The header line comes right after the metadata lines
+        let first_line = metadata.len() + 2;
+
+        Ok(MappedVcf {
+            map,
+            metadata,
+            individuals,
+            genotype,
+            columns,
+            data_start,
+            first_line,
+        })
+    }
+
+    pub fn metadata(&self) -> &[(String, String)] {
+        &self.metadata
+    }
+
+    pub fn individuals(&self) -> &[String] {
+        &self.individuals
+    }
+
+    /// The byte range of the data lines in the file
+    pub fn data(&self) -> Range<usize> {
+        self.data_start..self.map.len()
+    }
+
+    /// All the records of the file, in order
+    pub fn records(&self) -> Records<'_> {
+        Records {
+            vcf: self,
+            position: self.data_start,
+            end: self.map.len(),
+            line: Some(self.first_line),
+        }
+    }
+
+    /// The records that start within the given byte range, which must start
+    /// at the beginning of a data line, as the ranges of `offsets` and
+    /// `chunks` do. Line numbers are unknown in the errors of these records.
+    pub fn records_in(&self, range: Range<usize>) -> Result<Records<'_>, VcfError> {
+        self.check_offset(range.start)?;
+
+        Ok(Records {
+            vcf: self,
+            position: range.start,
+            end: range.end.min(self.map.len()),
+            line: None,
+        })
+    }
+
+    /// The record at the given byte offset, which must be the beginning of a
+    /// data line
+    pub fn record_at(&self, offset: usize) -> Result<VCFData<&str>, VcfError> {
+        match self.records_in(offset..offset + 1)?.next() {
+            Some(data) => data,
+            None => Err("Offset is past the last record".into()),
+        }
+    }
+
+    /// The byte offset of each data line, to later read it with `record_at`
+    pub fn offsets(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut position = self.data_start;
+        ::std::iter::from_fn(move || {
+            if position >= self.map.len() {
+                return None;
+            }
+            let offset = position;
+            position = self.line_end(position);
+            Some(offset)
+        })
+    }
+
+    /// Split the data lines into at most `count` byte ranges of about the
+    /// same size, each starting at the beginning of a line
+    pub fn chunks(&self, count: usize) -> Vec<Range<usize>> {
+        let end = self.map.len();
+        let size = (end - self.data_start) / count.max(1) + 1;
+
+        let mut chunks = Vec::with_capacity(count);
+        let mut start = self.data_start;
+        while start < end {
+            // Move the boundary forward to the beginning of the next line
+            let boundary = (start + size).min(end);
+            let boundary = match boundary {
+                boundary if boundary == end => end,
+                boundary => self.line_end(boundary - 1),
+            };
+            chunks.push(start..boundary);
+            start = boundary;
+        }
+
+        chunks
+    }
+
+    /// Split the file into one chunk per thread and call `scan` with the
+    /// records of each on its own thread, returning the results in the order
+    /// of the chunks
+    pub fn scan<F, T>(&self, threads: usize, scan: F) -> Vec<T>
+    where
+        F: Fn(Records<'_>) -> T + Sync,
+        T: Send,
+    {
+        let scan = &scan;
+        thread::scope(|scope| {
+            let handles = self
+                .chunks(threads)
+                .into_iter()
+                .map(|chunk| {
+                    let records = Records {
+                        vcf: self,
+                        position: chunk.start,
+                        end: chunk.end,
+                        line: None,
+                    };
+                    scope.spawn(move || scan(records))
+                })
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    }
+
+    /// The offset just after the line break that ends the line containing the
+    /// given offset
+    fn line_end(&self, position: usize) -> usize {
+        match memchr(b'\n', &self.map[position..]) {
+            Some(idx) => position + idx + 1,
+            None => self.map.len(),
+        }
+    }
+
+    fn check_offset(&self, offset: usize) -> Result<(), VcfError> {
+        let valid = offset >= self.data_start
+            && offset <= self.map.len()
+            && (offset == self.data_start || self.map[offset - 1] == b'\n');
+
+        if valid {
+            Ok(())
+        } else {
+            let message = format!("Offset {} is not the beginning of a data line", offset);
+            Err(VcfError::Invalid(message))
+        }
+    }
+}
+
+/// The records in part of a memory-mapped file. Malformed lines are reported
+/// and skipped.
+pub struct Records<'a> {
+    vcf: &'a MappedVcf,
+    position: usize,
+    end: usize,
+    line: Option<usize>,
+}
+
+impl<'a> Records<'a> {
+    /// The byte offset of the next record
+    pub fn offset(&self) -> usize {
+        self.position
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<VCFData<&'a str>, VcfError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.end {
+            return None;
+        }
+
+        let vcf = self.vcf;
+        let next = vcf.line_end(self.position);
+        let bytes = &vcf.map[self.position..next];
+        let line = self.line;
+
+        self.position = next;
+        if let Some(ref mut line) = self.line {
+            *line += 1;
+        }
+
+        Some(match str::from_utf8(bytes) {
+            Ok(text) => VCFData::parse_borrowed(text, vcf.genotype, vcf.columns, line),
+            Err(_) => {
+                let text = String::from_utf8_lossy(bytes);
+                let message = "Line is not valid UTF-8";
+                Err(VcfError::syntax(line, None, &text, message))
+            }
+        })
+    }
+}
+
+impl<'a> MetadataReader for &'a MappedVcf {
+    type Next = IndividualsReaderFromMmap<'a>;
+
+    fn read_metadata(self, map: &mut Vec<(String, String)>) -> Result<Self::Next, VcfError> {
+        map.extend(self.metadata.iter().cloned());
+        Ok(IndividualsReaderFromMmap { vcf: self })
+    }
+}
+
+pub struct IndividualsReaderFromMmap<'a> {
+    vcf: &'a MappedVcf,
+}
+
+impl<'a> IndividualsReader for IndividualsReaderFromMmap<'a> {
+    type Next = DataStreamFromMmap<'a>;
+
+    fn read_individuals(self, list: &mut Vec<String>) -> Result<Self::Next, VcfError> {
+        list.extend(self.vcf.individuals.iter().cloned());
+        Ok(DataStreamFromMmap {
+            records: self.vcf.records(),
+        })
+    }
+}
+
+pub struct DataStreamFromMmap<'a> {
+    records: Records<'a>,
+}
+
+impl<'a> Iterator for DataStreamFromMmap<'a> {
+    type Item = Result<VCFData, VcfError>;
+
+    fn next(&mut self) -> Option<Result<VCFData, VcfError>> {
+        let data = self.records.next()?;
+        Some(data.map(VCFData::into_owned))
+    }
+}
+
+impl<'a> DataStream for DataStreamFromMmap<'a> {}
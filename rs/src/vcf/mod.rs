@@ -1,4 +1,21 @@
+pub mod bcf;
+pub mod bgzf;
+pub mod cache;
+pub mod concat;
+pub mod error;
+pub mod genotype;
+pub mod header;
+pub mod index;
+pub mod matrix;
+pub mod mmap;
+pub mod normalize;
+pub mod plink;
 pub mod stream;
+pub mod validate;
+pub mod variant;
 pub mod population;
 pub mod filter;
 pub mod predictor;
+pub mod qc;
+pub mod region;
+pub mod writer;
@@ -0,0 +1,165 @@
+use vcf::filter::{DataAction, Filter};
+use vcf::header::{Header, Number};
+use vcf::stream::{VCFColumn, VCFData};
+
+/// Split records with more than one alternate allele into one biallelic
+/// record per alternate allele, like `bcftools norm -m-`.
+///
+/// Fields with one value per alternate allele, per allele or per genotype, as
+/// declared in the header, keep only the values for the reference and the
+/// allele of each record. In GT, that allele becomes `1` and the other
+/// alternate alleles become the reference. Fields the header does not declare
+/// are copied unchanged.
+#[derive(Default)]
+pub struct SplitAllelesFilter {
+    header: Header,
+}
+
+impl SplitAllelesFilter {
+    pub fn new() -> Self {
+        SplitAllelesFilter {
+            header: Header::default(),
+        }
+    }
+
+    fn split(&self, item: &VCFData, allele: usize) -> VCFData {
+        let alleles = item.alternative_count() + 1;
+        let number = |definition: Option<Number>| definition.unwrap_or(Number::Unknown);
+
+        let mut fields = item
+            .fields()
+            .take(VCFColumn::INFO as usize)
+            .map(|field| field.to_string())
+            .collect::<Vec<_>>();
+        fields[VCFColumn::ALT as usize] = item.alternatives()[allele - 1].to_string();
+
+        let info = if item.info() == "." {
+            item.info().to_string()
+        } else {
+            item.info()
+                .split(';')
+                .map(|field| {
+                    let mut parts = field.splitn(2, '=');
+                    let key = parts.next().unwrap_or_default();
+                    match parts.next() {
+                        Some(value) => {
+                            let definition = self.info_number(key);
+                            let value = split_values(value, number(definition), allele, alleles);
+                            format!("{}={}", key, value)
+                        }
+                        None => field.to_string(),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(";")
+        };
+        fields.push(info);
+
+        let format = item.format();
+        fields.push(format.to_string());
+
+        let numbers = format
+            .split(':')
+            .map(|key| (key == "GT", number(self.format_number(key))))
+            .collect::<Vec<_>>();
+
+        for sample in item.genotypes() {
+            let sample = sample
+                .split(':')
+                .zip(&numbers)
+                .map(|(value, &(gt, number))| {
+                    if gt {
+                        split_gt(value, allele)
+                    } else {
+                        split_values(value, number, allele, alleles)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(":");
+            fields.push(sample);
+        }
+
+        VCFData::from_fields(fields)
+    }
+
+    fn info_number(&self, key: &str) -> Option<Number> {
+        Some(self.header.info(key)?.number)
+    }
+
+    fn format_number(&self, key: &str) -> Option<Number> {
+        Some(self.header.format(key)?.number)
+    }
+}
+
+impl Filter for SplitAllelesFilter {
+    fn filter_metadata(&mut self, metadata: Vec<(String, String)>) -> Vec<(String, String)> {
+        self.header = Header::parse(&metadata);
+        metadata
+    }
+
+    fn filter_item(&mut self, item: VCFData) -> DataAction {
+        let count = item.alternative_count();
+        if count < 2 {
+            return DataAction::Data(item);
+        }
+
+        DataAction::Split(
+            (1..=count)
+                .map(|allele| self.split(&item, allele))
+                .collect(),
+        )
+    }
+}
+
+/// Keep the values of a field that pertain to the reference and the given
+/// allele. Values whose count does not match the number of alleles are kept
+/// unchanged, except for genotype fields of unknown ploidy, which become
+/// missing.
+fn split_values(raw: &str, number: Number, allele: usize, alleles: usize) -> String {
+    if raw == "." {
+        return raw.to_string();
+    }
+
+    let values = raw.split(',').collect::<Vec<_>>();
+
+    match number {
+        Number::AlternateAlleles if values.len() == alleles - 1 => values[allele - 1].to_string(),
+        Number::Alleles if values.len() == alleles => format!("{},{}", values[0], values[allele]),
+        Number::Genotypes if values.len() == alleles => {
+            // Haploid, with one value per allele
+            format!("{},{}", values[0], values[allele])
+        }
+        Number::Genotypes if values.len() == alleles * (alleles + 1) / 2 => {
+            // Diploid, where genotype `a/b` with `a <= b` is at `b(b+1)/2 + a`
+            let index = |a: usize, b: usize| b * (b + 1) / 2 + a;
+            format!(
+                "{},{},{}",
+                values[index(0, 0)],
+                values[index(0, allele)],
+                values[index(allele, allele)]
+            )
+        }
+        Number::Genotypes => ".".to_string(),
+        _ => raw.to_string(),
+    }
+}
+
+/// Rewrite a GT value so that `allele` becomes `1` and the other alternate
+/// alleles become `0`, keeping separators and missing alleles
+fn split_gt(gt: &str, allele: usize) -> String {
+    let mut result = String::with_capacity(gt.len());
+    let mut start = 0;
+
+    for (end, separator) in gt.match_indices(['/', '|']).chain(Some((gt.len(), ""))) {
+        let index = &gt[start..end];
+        result.push_str(match index.parse::<usize>() {
+            Ok(index) if index == allele => "1",
+            Ok(_) => "0",
+            Err(_) => index,
+        });
+        result.push_str(separator);
+        start = end + separator.len();
+    }
+
+    result
+}
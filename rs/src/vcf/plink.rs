@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Lines, Read};
+use std::path::{Path, PathBuf};
+
+use vcf::error::VcfError;
+use vcf::population::{Population, Sex};
+use vcf::stream::{DataStream, IndividualsReader, MetadataReader, VCFData};
+
+/// The bytes every `.bed` file starts with
+const BED_MAGIC: [u8; 2] = [0x6c, 0x1b];
+
+/// The mode byte of `.bed` files where the calls of each variant are together
+const VARIANT_MAJOR: u8 = 0x01;
+
+/// The GT of each 2-bit code of a `.bed` file, where the reference is A2 and
+/// the alternate allele is A1, as in `plink --recode vcf`
+const CALLS: [&str; 4] = ["1/1", "./.", "0/1", "0/0"];
+
+/// A PLINK binary fileset: the calls in a `.bed` file, the variants in a
+/// `.bim` file and the samples in a `.fam` file.
+///
+/// The fileset is read as a stream of VCF records, whose only FORMAT field is
+/// GT. Following `plink --recode vcf`, A2 is the reference allele and A1 the
+/// alternate allele, and the numeric chromosome codes 23 to 26 become X, Y, X
+/// and MT.
+pub struct Plink {
+    bed: PathBuf,
+    bim: PathBuf,
+    fam: PathBuf,
+}
+
+/// A line of a `.fam` file
+#[derive(Debug, Clone)]
+pub struct FamSample {
+    pub family: String,
+    pub individual: String,
+    pub father: String,
+    pub mother: String,
+    pub sex: Sex,
+}
+
+impl Plink {
+    /// The fileset whose files are named by adding the extensions to the
+    /// given prefix
+    pub fn new<P: AsRef<Path>>(prefix: P) -> Plink {
+        let prefix = prefix.as_ref().as_os_str();
+        let with_extension = |extension: &str| {
+            let mut path = prefix.to_os_string();
+            path.push(extension);
+            PathBuf::from(path)
+        };
+
+        Plink {
+            bed: with_extension(".bed"),
+            bim: with_extension(".bim"),
+            fam: with_extension(".fam"),
+        }
+    }
+
+    pub fn from_paths<P: Into<PathBuf>>(bed: P, bim: P, fam: P) -> Plink {
+        Plink {
+            bed: bed.into(),
+            bim: bim.into(),
+            fam: fam.into(),
+        }
+    }
+
+    /// The samples listed in the `.fam` file
+    pub fn samples(&self) -> Result<Vec<FamSample>, VcfError> {
+        let in_file = |e: VcfError| e.in_file(&self.fam);
+        let reader = BufReader::new(File::open(&self.fam).map_err(|e| in_file(e.into()))?);
+        read_fam(reader).map_err(in_file)
+    }
+
+    /// The population whose groups are the families of the `.fam` file
+    pub fn population(&self) -> Result<Population, VcfError> {
+        let mut population = Population::new();
+        for sample in self.samples()? {
+            population.add_individual(sample.individual, sample.family);
+        }
+        Ok(population)
+    }
+
+    /// The sex of each sample of the `.fam` file
+    pub fn sexes(&self) -> Result<HashMap<String, Sex>, VcfError> {
+        let samples = self.samples()?;
+        Ok(samples.into_iter().map(|s| (s.individual, s.sex)).collect())
+    }
+}
+
+/// Read the samples of a `.fam` file, which has one line per sample with its
+/// family, its identifier, its father, its mother, its sex and a phenotype
+pub fn read_fam<R: BufRead>(reader: R) -> Result<Vec<FamSample>, VcfError> {
+    let mut samples = Vec::new();
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| VcfError::io(Some(idx + 1), e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.len() < 5 {
+            let message = format!("Line has {} columns but needs at least 5", fields.len());
+            return Err(VcfError::syntax(Some(idx + 1), None, &line, message));
+        }
+
+        samples.push(FamSample {
+            family: fields[0].to_string(),
+            individual: fields[1].to_string(),
+            father: fields[2].to_string(),
+            mother: fields[3].to_string(),
+            sex: Sex::from_code(fields[4]),
+        });
+    }
+
+    Ok(samples)
+}
+
+impl MetadataReader for Plink {
+    type Next = IndividualsReaderFromPlink;
+
+    fn read_metadata(self, metadata: &mut Vec<(String, String)>) -> Result<Self::Next, VcfError> {
+        metadata.push(("fileformat".to_string(), "VCFv4.2".to_string()));
+        metadata.push((
+            "FORMAT".to_string(),
+            "<ID=GT,Number=1,Type=String,Description=\"Genotype\">".to_string(),
+        ));
+
+        Ok(IndividualsReaderFromPlink { plink: self })
+    }
+}
+
+pub struct IndividualsReaderFromPlink {
+    plink: Plink,
+}
+
+impl IndividualsReader for IndividualsReaderFromPlink {
+    type Next = DataStreamFromPlink;
+
+    fn read_individuals(self, list: &mut Vec<String>) -> Result<Self::Next, VcfError> {
+        let plink = self.plink;
+        let start_len = list.len();
+        let samples = plink.samples()?;
+        let stride = samples.len().div_ceil(4);
+
+        let bim = File::open(&plink.bim).map_err(|e| VcfError::from(e).in_file(&plink.bim))?;
+        // Blank lines are skipped when reading, so they hold no variant
+        let variants = BufReader::new(bim)
+            .lines()
+            .filter(|line| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+            .count();
+
+        let in_bed = |e: VcfError| e.in_file(&plink.bed);
+        let mut bed = BufReader::new(File::open(&plink.bed).map_err(|e| in_bed(e.into()))?);
+        let mut magic = [0; 3];
+        bed.read_exact(&mut magic).map_err(|e| in_bed(e.into()))?;
+        if magic[..2] != BED_MAGIC {
+            return Err(in_bed("Not a PLINK .bed file".into()));
+        }
+        if magic[2] != VARIANT_MAJOR {
+            return Err(in_bed("Only variant-major .bed files are supported".into()));
+        }
+
+        // The size of the file tells whether it matches the other two
+        let len = fs::metadata(&plink.bed)
+            .map_err(|e| in_bed(e.into()))?
+            .len();
+        let expected = (3 + variants * stride) as u64;
+        if len != expected {
+            let message = format!(
+                "File has {} bytes, but {} variants of {} samples need {}",
+                len,
+                variants,
+                samples.len(),
+                expected
+            );
+            return Err(in_bed(VcfError::Invalid(message)));
+        }
+
+        let bim = File::open(&plink.bim).map_err(|e| VcfError::from(e).in_file(&plink.bim))?;
+
+        list.extend(samples.into_iter().map(|s| s.individual));
+
+        Ok(DataStreamFromPlink {
+            bim: BufReader::new(bim).lines(),
+            bed,
+            buffer: vec![0; stride],
+            samples: list.len() - start_len,
+            line_number: 0,
+            failed: false,
+            plink,
+        })
+    }
+}
+
+pub struct DataStreamFromPlink {
+    plink: Plink,
+    bim: Lines<BufReader<File>>,
+    bed: BufReader<File>,
+    buffer: Vec<u8>,
+    samples: usize,
+    line_number: usize,
+    failed: bool,
+}
+
+impl DataStreamFromPlink {
+    fn read_next(&mut self) -> Result<Option<VCFData>, VcfError> {
+        let line = loop {
+            let line = match self.bim.next() {
+                Some(line) => line,
+                None => return Ok(None),
+            };
+            self.line_number += 1;
+
+            let line = line
+                .map_err(|e| VcfError::io(Some(self.line_number), e))
+                .map_err(|e| e.in_file(&self.plink.bim))?;
+            if !line.trim().is_empty() {
+                break line;
+            }
+        };
+
+        let line_number = Some(self.line_number);
+
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 6 {
+            let message = format!("Line has {} columns but needs 6", fields.len());
+            let error = VcfError::syntax(line_number, None, &line, message);
+            return Err(error.in_file(&self.plink.bim));
+        }
+
+        self.bed
+            .read_exact(&mut self.buffer)
+            .map_err(|e| VcfError::from(e).in_file(&self.plink.bed))?;
+
+        let allele = |allele: &str, missing: &'static str| match allele {
+            "0" => missing.to_string(),
+            allele => allele.to_string(),
+        };
+        let record = vec![
+            chromosome(fields[0]).to_string(),
+            fields[3].to_string(),
+            fields[1].to_string(),
+            allele(fields[5], "N"),
+            allele(fields[4], "."),
+            ".".to_string(),
+            ".".to_string(),
+            ".".to_string(),
+            "GT".to_string(),
+        ];
+
+        let buffer = &self.buffer;
+        let calls = (0..self.samples).map(|idx| {
+            let code = (buffer[idx / 4] >> (2 * (idx % 4))) & 0b11;
+            CALLS[code as usize].to_string()
+        });
+
+        let fields = record.into_iter().chain(calls);
+        Ok(Some(VCFData::from_fields(fields)))
+    }
+}
+
+impl Iterator for DataStreamFromPlink {
+    type Item = Result<VCFData, VcfError>;
+
+    fn next(&mut self) -> Option<Result<VCFData, VcfError>> {
+        if self.failed {
+            return None;
+        }
+
+        let result = self.read_next();
+        self.failed = result.is_err();
+        result.transpose()
+    }
+}
+
+impl DataStream for DataStreamFromPlink {}
+
+/// The VCF name of a PLINK chromosome code
+fn chromosome(code: &str) -> &str {
+    match code {
+        "23" | "25" | "XY" => "X",
+        "24" => "Y",
+        "26" => "MT",
+        code => code,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    const BIM: &str = "1\trs1\t0\t100\tG\tA\n23\trs2\t0\t200\tT\t0\n";
+    const FAM: &str = "F1 S1 0 0 1 -9\nF1 S2 0 0 2 -9\nF2 S3 0 0 0 -9\n\
+                       F2 S4 0 0 1 -9\nF3 S5 0 0 2 -9\n";
+
+    /// Five samples, so that the second byte of each variant holds a single
+    /// call and padding, which is set to make sure it is ignored
+    const BED: [u8; 7] = [0x6c, 0x1b, 0x01, 0xe4, 0xfc, 0xff, 0xaa];
+
+    /// Write a fileset under a name of its own, read it and delete it
+    fn with_fileset<T, F>(name: &str, bed: &[u8], read: F) -> T
+    where
+        F: FnOnce(Plink) -> T,
+    {
+        let prefix = env::temp_dir().join(format!("rs-plink-{}-{}", process::id(), name));
+        let plink = Plink::new(&prefix);
+        fs::write(&plink.bed, bed).unwrap();
+        fs::write(&plink.bim, BIM).unwrap();
+        fs::write(&plink.fam, FAM).unwrap();
+
+        let paths = vec![plink.bed.clone(), plink.bim.clone(), plink.fam.clone()];
+        let result = read(plink);
+        for path in paths {
+            fs::remove_file(path).unwrap();
+        }
+        result
+    }
+
+    #[test]
+    fn reads_every_code() {
+        let records = with_fileset("codes", &BED, |plink| {
+            let unfolded = plink.unfold().unwrap();
+            assert_eq!(unfolded.individuals, vec!["S1", "S2", "S3", "S4", "S5"]);
+            unfolded
+                .stream
+                .map(|data| data.unwrap().fields().map(String::from).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        });
+
+        let expected = [
+            "1\t100\trs1\tA\tG\t.\t.\t.\tGT\t1/1\t./.\t0/1\t0/0\t1/1",
+            "X\t200\trs2\tN\tT\t.\t.\t.\tGT\t0/0\t0/0\t0/0\t0/0\t0/1",
+        ];
+        assert_eq!(records.len(), expected.len());
+        for (record, expected) in records.iter().zip(&expected) {
+            assert_eq!(record.join("\t"), *expected);
+        }
+    }
+
+    #[test]
+    fn checks_the_size_and_magic_of_the_bed_file() {
+        let error = with_fileset("short", &BED[..6], |plink| plink.unfold().err());
+        let error = error.unwrap().to_string();
+        assert!(error.contains("File has 6 bytes, but 2 variants of 5 samples need 7"));
+
+        let mut bed = BED;
+        bed[1] = 0x1c;
+        let error = with_fileset("magic", &bed, |plink| plink.unfold().err());
+        assert!(error.unwrap().to_string().contains("Not a PLINK .bed file"));
+
+        let mut bed = BED;
+        bed[2] = 0x00;
+        let error = with_fileset("mode", &bed, |plink| plink.unfold().err());
+        assert!(error.unwrap().to_string().contains("Only variant-major"));
+    }
+}
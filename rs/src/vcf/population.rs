@@ -2,6 +2,7 @@ use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::io::{BufRead, Result, Error, ErrorKind};
 
+#[derive(Clone)]
 pub struct Population {
     individual_to_group: HashMap<String, String>,
     group_to_individuals: HashMap<String, Vec<String>>,
@@ -72,7 +73,7 @@ impl Population {
         
         for line in reader.lines() {
             let line = line?;
-            let line = line.trim_right_matches('\n');
+            let line = line.trim_end_matches('\n');
             
             // Remove possible comments
             let line = if let Some(comment_start) = line.find('#') {
@@ -85,7 +86,7 @@ impl Population {
             let line = line.trim();
             
             // Ignore empty lines
-            if line.len() == 0 {
+            if line.is_empty() {
                 continue;
             }
             
@@ -104,3 +105,89 @@ impl Population {
     }
 }
 
+impl Default for Population {
+    fn default() -> Self {
+        Population::new()
+    }
+}
+
+/// The sex of an individual
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sex {
+    Male,
+    Female,
+    Unknown,
+}
+
+impl Sex {
+    /// Convert the code used in PED files and in the 1000 Genomes sample
+    /// list, where 1 is male and 2 is female
+    pub fn from_code(code: &str) -> Sex {
+        match code.trim() {
+            "1" => Sex::Male,
+            "2" => Sex::Female,
+            _ => Sex::Unknown,
+        }
+    }
+}
+
+/// Read the sex of each individual from a CSV file with a header line naming
+/// its columns, such as `populations/all.csv`, where they are found in the
+/// "Individual ID" and "Gender" columns
+pub fn read_sexes<R: BufRead>(reader: R) -> Result<HashMap<String, Sex>> {
+    let mut lines = reader.lines();
+
+    let header = match lines.next() {
+        Some(line) => split_csv(&line?),
+        None => return Ok(HashMap::new()),
+    };
+
+    let column = |name: &str| {
+        header.iter().position(|h| h == name).ok_or_else(|| {
+            let msg = format!("CSV file has no {:?} column", name);
+            Error::new(ErrorKind::InvalidData, msg)
+        })
+    };
+    let individual_column = column("Individual ID")?;
+    let sex_column = column("Gender")?;
+
+    let mut sexes = HashMap::new();
+
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields = split_csv(&line);
+        match (fields.get(individual_column), fields.get(sex_column)) {
+            (Some(individual), Some(sex)) => {
+                sexes.insert(individual.to_string(), Sex::from_code(sex));
+            }
+            _ => {
+                let msg = format!("Line {:?} is invalid: too few fields", line);
+                return Err(Error::new(ErrorKind::InvalidData, msg));
+            }
+        }
+    }
+
+    Ok(sexes)
+}
+
+/// Split a line of a CSV file into its fields, which may be quoted
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+
+    for c in line.trim_end_matches(['\n', '\r']).chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(::std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+
+    fields
+}
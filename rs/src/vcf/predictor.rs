@@ -8,9 +8,11 @@ pub trait PredictorFactory
 {
     type Predictor: Predictor;
     
+    /// Build a predictor from the genotype, the ploidy and the label of each
+    /// training sample. Genotypes are `None` when missing.
     fn build<I>(&self, genotypes_and_labels: I) -> Self::Predictor
     where
-        I: Iterator<Item = (<Self::Predictor as Predictor>::Prediction, String)>;
+        I: Iterator<Item = (Option<<Self::Predictor as Predictor>::Prediction>, usize, String)>;
 }
 
 pub trait Predictor {
@@ -34,11 +36,16 @@ impl Predictor for PredictorJoao {
             .map(|(label, freq)| (&**label, f32::abs(genotype - freq)))
             .sorted_by(|(_, f1), (_, f2)| f1.partial_cmp(f2).unwrap());
 
+        // Labels whose training genotypes were all missing have no frequency
+        if distances.len() < 2 {
+            return distances.iter().map(|&(label, _)| (label, 1.0)).collect();
+        }
+
         if distances[1].1 - distances[0].1 >= self.threshold {
-            vec![(distances[0].0, 1.0)].iter().cloned().collect()
+            [(distances[0].0, 1.0)].iter().cloned().collect()
         }
         else {
-            vec![].iter().cloned().collect()
+            HashMap::new()
         }
     }
 }
@@ -52,7 +59,7 @@ impl PredictorFactory for PredictorJoaoFactory {
     
     fn build<I>(&self, genotypes_and_labels: I) -> PredictorJoao
     where
-        I: Iterator<Item = (f32, String)>,
+        I: Iterator<Item = (Option<f32>, usize, String)>,
     {
         PredictorJoao {
             threshold: self.threshold,
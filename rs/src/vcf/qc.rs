@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use vcf::error::VcfError;
+use vcf::filter::{DataAction, Filter};
+use vcf::genotype::Genotype;
+use vcf::population::Population;
+use vcf::stream::{MetadataReader, VCFData};
+
+/// Keep the records whose minor allele frequency and count, computed from the
+/// calls of the samples in the stream, are within a range, like PLINK's
+/// `--maf` and `--mac`. The minor allele is the second most common one, so
+/// that multi-allelic sites are judged by their most common alternate allele.
+///
+/// When given a population, the frequency is computed within each of its
+/// groups and a record is kept if it is within the range in any of them, so
+/// that variants common in a single group are not lost. Individuals that do
+/// not belong to the population are then not counted.
+pub struct FrequencyFilter {
+    min_frequency: f64,
+    max_frequency: f64,
+    min_count: usize,
+    max_count: usize,
+    population: Option<Population>,
+
+    // The group of each sample in the stream, as an index into the counts
+    groups: Vec<Option<usize>>,
+    group_count: usize,
+}
+
+impl FrequencyFilter {
+    pub fn new() -> FrequencyFilter {
+        FrequencyFilter {
+            min_frequency: 0.0,
+            max_frequency: 0.5,
+            min_count: 0,
+            max_count: usize::MAX,
+            population: None,
+            groups: Vec::new(),
+            group_count: 0,
+        }
+    }
+
+    pub fn min_frequency(mut self, frequency: f64) -> FrequencyFilter {
+        self.min_frequency = frequency;
+        self
+    }
+
+    pub fn max_frequency(mut self, frequency: f64) -> FrequencyFilter {
+        self.max_frequency = frequency;
+        self
+    }
+
+    pub fn min_count(mut self, count: usize) -> FrequencyFilter {
+        self.min_count = count;
+        self
+    }
+
+    pub fn max_count(mut self, count: usize) -> FrequencyFilter {
+        self.max_count = count;
+        self
+    }
+
+    /// Compute the frequencies within each group of the population
+    pub fn by_population(mut self, population: Population) -> FrequencyFilter {
+        self.population = Some(population);
+        self
+    }
+
+    fn in_range(&self, counts: &mut [usize]) -> bool {
+        let total = counts.iter().sum::<usize>();
+        if total == 0 {
+            return false;
+        }
+
+        counts.sort_unstable_by(|a, b| b.cmp(a));
+        let count = counts.get(1).cloned().unwrap_or(0);
+        let frequency = count as f64 / total as f64;
+
+        self.min_count <= count
+            && count <= self.max_count
+            && self.min_frequency <= frequency
+            && frequency <= self.max_frequency
+    }
+}
+
+impl Default for FrequencyFilter {
+    fn default() -> Self {
+        FrequencyFilter::new()
+    }
+}
+
+impl Filter for FrequencyFilter {
+    fn filter_individuals(&mut self, individuals: Vec<String>) -> Vec<String> {
+        let (groups, names) = sample_groups(&individuals, self.population.as_ref());
+        self.groups = groups;
+        self.group_count = names.len();
+        individuals
+    }
+
+    fn filter_item(&mut self, item: VCFData) -> DataAction {
+        let alleles = item.alternative_count() + 1;
+        let mut counts = vec![vec![0; alleles]; self.group_count];
+
+        for (call, &group) in item.calls().zip(&self.groups) {
+            if let (Some(call), Some(group)) = (call, group) {
+                for &allele in call.alleles().iter().flatten() {
+                    if allele < alleles {
+                        counts[group][allele] += 1;
+                    }
+                }
+            }
+        }
+
+        if counts.iter_mut().any(|counts| self.in_range(counts)) {
+            DataAction::Data(item)
+        } else {
+            DataAction::Ignore
+        }
+    }
+}
+
+/// Keep the records in which at least a fraction of the samples were called,
+/// like PLINK's `--geno`. A call with any missing allele counts as missing.
+/// Records without samples are kept.
+pub struct CallRateFilter {
+    min_rate: f64,
+}
+
+impl CallRateFilter {
+    pub fn new(min_rate: f64) -> CallRateFilter {
+        CallRateFilter { min_rate }
+    }
+}
+
+impl Filter for CallRateFilter {
+    fn filter_item(&mut self, item: VCFData) -> DataAction {
+        let (mut called, mut total) = (0, 0);
+        for call in item.calls() {
+            total += 1;
+            called += is_called(call) as usize;
+        }
+
+        if total == 0 || called as f64 >= self.min_rate * total as f64 {
+            DataAction::Data(item)
+        } else {
+            DataAction::Ignore
+        }
+    }
+}
+
+/// Drop the records that deviate from Hardy-Weinberg equilibrium, by the exact
+/// test of Wigginton et al. (2005), like PLINK's `--hwe`. Only the diploid
+/// calls of biallelic records are tested, so multi-allelic sites should be
+/// split first; other records are kept.
+///
+/// When given a population, the test is done within each of its groups, as
+/// mixing groups with different frequencies makes sites look out of
+/// equilibrium, and a record is dropped if it fails in any of them.
+/// Individuals that do not belong to the population are then not tested.
+pub struct HweFilter {
+    threshold: f64,
+    population: Option<Population>,
+    report: Option<Box<dyn Write>>,
+
+    groups: Vec<Option<usize>>,
+    names: Vec<String>,
+}
+
+impl HweFilter {
+    /// Drop the records with a p-value below the threshold
+    pub fn new(threshold: f64) -> HweFilter {
+        HweFilter {
+            threshold,
+            population: None,
+            report: None,
+            groups: Vec::new(),
+            names: Vec::new(),
+        }
+    }
+
+    /// Test within each group of the population
+    pub fn by_population(mut self, population: Population) -> HweFilter {
+        self.population = Some(population);
+        self
+    }
+
+    /// Write the genotype counts and p-value of each tested site and group to
+    /// the writer, as tab-separated values. If writing fails, a warning is
+    /// printed and the report is abandoned, but the stream goes on.
+    pub fn report<W: Write + 'static>(mut self, writer: W) -> HweFilter {
+        self.report = Some(Box::new(writer));
+        self
+    }
+}
+
+impl Filter for HweFilter {
+    fn filter_individuals(&mut self, individuals: Vec<String>) -> Vec<String> {
+        let (groups, names) = sample_groups(&individuals, self.population.as_ref());
+        self.groups = groups;
+        self.names = names;
+
+        write_report(&mut self.report, |report| {
+            writeln!(report, "chrom\tpos\tid\tgroup\thom_ref\thet\thom_alt\tp")
+        });
+
+        individuals
+    }
+
+    fn filter_item(&mut self, item: VCFData) -> DataAction {
+        if item.alternative_count() != 1 {
+            return DataAction::Data(item);
+        }
+
+        // The number of homozygous reference, heterozygous and homozygous
+        // alternate calls of each group
+        let mut counts = vec![[0; 3]; self.names.len()];
+        for (call, &group) in item.calls().zip(&self.groups) {
+            if let (Some(call), Some(group)) = (call, group) {
+                if call.ploidy() == 2 {
+                    if let Some(dosage) = call.dosage() {
+                        counts[group][dosage] += 1;
+                    }
+                }
+            }
+        }
+
+        let mut keep = true;
+        for (group, &[hom_ref, het, hom_alt]) in counts.iter().enumerate() {
+            if hom_ref + het + hom_alt == 0 {
+                continue;
+            }
+
+            let p = hwe_exact(het, hom_ref, hom_alt);
+            keep &= p >= self.threshold;
+
+            let name = &self.names[group];
+            write_report(&mut self.report, |report| {
+                writeln!(
+                    report,
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:e}",
+                    item.chromosome(),
+                    item.position(),
+                    item.identifier(),
+                    name,
+                    hom_ref,
+                    het,
+                    hom_alt,
+                    p
+                )
+            });
+        }
+
+        if keep {
+            DataAction::Data(item)
+        } else {
+            DataAction::Ignore
+        }
+    }
+}
+
+/// Write to a report, abandoning it with a warning if writing fails
+fn write_report<F>(report: &mut Option<Box<dyn Write>>, write: F)
+where
+    F: FnOnce(&mut dyn Write) -> ::std::io::Result<()>,
+{
+    if let Some(ref mut writer) = *report {
+        if let Err(e) = write(writer) {
+            eprintln!("Warning: could not write the HWE report: {}", e);
+            *report = None;
+        }
+    }
+}
+
+/// The p-value of the exact test of Hardy-Weinberg equilibrium of Wigginton et
+/// al. (2005) for a biallelic site with the given genotype counts: the
+/// probability, given the allele counts, of a number of heterozygotes at most
+/// as likely as the observed one.
+pub fn hwe_exact(het: usize, hom_ref: usize, hom_alt: usize) -> f64 {
+    let genotypes = het + hom_ref + hom_alt;
+    if genotypes == 0 {
+        return 1.0;
+    }
+
+    let rare = 2 * hom_ref.min(hom_alt) + het;
+
+    // Start at the most likely number of heterozygotes, which has the same
+    // parity as the number of rare alleles, and compute the probabilities of
+    // the others relative to it
+    let mut probabilities = vec![0.0; rare + 1];
+    let mut mid = rare * (2 * genotypes - rare) / (2 * genotypes);
+    if mid % 2 != rare % 2 {
+        mid += 1;
+    }
+    probabilities[mid] = 1.0;
+
+    let start = (mid, (rare - mid) / 2, genotypes - mid - (rare - mid) / 2);
+
+    let (mut hets, mut homr, mut homc) = start;
+    while hets >= 2 {
+        let ratio = (hets * (hets - 1)) as f64 / (4 * (homr + 1) * (homc + 1)) as f64;
+        probabilities[hets - 2] = probabilities[hets] * ratio;
+        hets -= 2;
+        homr += 1;
+        homc += 1;
+    }
+
+    let (mut hets, mut homr, mut homc) = start;
+    while hets + 2 <= rare {
+        let ratio = (4 * homr * homc) as f64 / ((hets + 2) * (hets + 1)) as f64;
+        probabilities[hets + 2] = probabilities[hets] * ratio;
+        hets += 2;
+        homr -= 1;
+        homc -= 1;
+    }
+
+    let observed = probabilities[het];
+    let total = probabilities.iter().sum::<f64>();
+    let p = probabilities
+        .iter()
+        .filter(|&&probability| probability <= observed)
+        .sum::<f64>();
+
+    (p / total).min(1.0)
+}
+
+/// The fraction of missing calls of each individual in a stream, for a first
+/// pass of sample QC like PLINK's `--mind`. The individuals to keep can then
+/// be given to an `IndividualsFilter` in a second pass over the data.
+#[derive(Debug, Clone)]
+pub struct SampleMissingness {
+    individuals: Vec<String>,
+    missing: Vec<usize>,
+    records: usize,
+}
+
+impl SampleMissingness {
+    /// Read the whole stream, counting the missing calls of each individual
+    pub fn compute<S: MetadataReader>(stream: S) -> Result<SampleMissingness, VcfError> {
+        let unfolded = stream.unfold()?;
+        let mut missing = vec![0; unfolded.individuals.len()];
+        let mut records = 0;
+
+        for data in unfolded.stream {
+            let data = data?;
+            records += 1;
+            for (count, call) in missing.iter_mut().zip(data.calls()) {
+                *count += !is_called(call) as usize;
+            }
+        }
+
+        Ok(SampleMissingness {
+            individuals: unfolded.individuals,
+            missing,
+            records,
+        })
+    }
+
+    pub fn individuals(&self) -> &[String] {
+        &self.individuals
+    }
+
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    /// The fraction of records in which the individual at the given index was
+    /// not called, which is 0 if there were no records
+    pub fn rate(&self, index: usize) -> f64 {
+        if self.records == 0 {
+            return 0.0;
+        }
+        self.missing[index] as f64 / self.records as f64
+    }
+
+    /// The individuals missing more than the given fraction of calls
+    pub fn excluded(&self, max_rate: f64) -> Vec<&str> {
+        self.select(|rate| rate > max_rate)
+    }
+
+    /// The individuals missing at most the given fraction of calls, to be
+    /// given to `IndividualsFilter::new`
+    pub fn retained(&self, max_rate: f64) -> Vec<&str> {
+        self.select(|rate| rate <= max_rate)
+    }
+
+    fn select<F: Fn(f64) -> bool>(&self, predicate: F) -> Vec<&str> {
+        self.individuals
+            .iter()
+            .enumerate()
+            .filter(|&(idx, _)| predicate(self.rate(idx)))
+            .map(|(_, individual)| individual.as_str())
+            .collect()
+    }
+}
+
+fn is_called(call: Option<Genotype>) -> bool {
+    call.is_some_and(|call| !call.has_missing())
+}
+
+/// The index of the group of each individual and the names of the groups.
+/// With no population, every individual is in a single group named `ALL`.
+fn sample_groups(
+    individuals: &[String],
+    population: Option<&Population>,
+) -> (Vec<Option<usize>>, Vec<String>) {
+    let population = match population {
+        Some(population) => population,
+        None => return (vec![Some(0); individuals.len()], vec!["ALL".to_string()]),
+    };
+
+    let mut names = Vec::new();
+    let mut indices = HashMap::new();
+    let groups = individuals
+        .iter()
+        .map(|individual| {
+            if !population.has_individual(individual) {
+                return None;
+            }
+            let group = population.group(individual);
+            Some(*indices.entry(group).or_insert_with(|| {
+                names.push(group.to_string());
+                names.len() - 1
+            }))
+        })
+        .collect();
+
+    (groups, names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hwe_exact;
+
+    #[test]
+    fn hwe_exact_matches_wigginton() {
+        // The p-values of the reference implementation of Wigginton et al.
+        let cases = [
+            ((57, 14, 50), 0.842280),
+            ((0, 10, 10), 1.340302e-6),
+            ((3, 5, 7), 0.032007),
+        ];
+        for &((het, hom_ref, hom_alt), expected) in &cases {
+            let p = hwe_exact(het, hom_ref, hom_alt);
+            let error = (p - expected).abs() / expected;
+            assert!(error < 1e-5, "{} != {}", p, expected);
+        }
+    }
+
+    #[test]
+    fn hwe_exact_without_genotypes() {
+        assert_eq!(hwe_exact(0, 0, 0), 1.0);
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+
+use vcf::error::VcfError;
+use vcf::filter::{DataAction, Filter};
+use vcf::index::{record_end, Region};
+use vcf::stream::VCFData;
+
+/// The intervals of a contig, sorted by start, along with the largest end of
+/// each interval and the ones before it. All the intervals that start before
+/// a position are found with a binary search, and whether any of them
+/// reaches another position is then known from the largest end, so that
+/// overlaps are found in logarithmic time, as with an interval tree.
+#[derive(Debug, Default)]
+struct IntervalTree {
+    intervals: Vec<(u64, u64)>,
+    max_ends: Vec<u64>,
+}
+
+impl IntervalTree {
+    fn new(mut intervals: Vec<(u64, u64)>) -> IntervalTree {
+        intervals.sort_unstable();
+
+        let max_ends = intervals
+            .iter()
+            .scan(0, |max, &(_, end)| {
+                *max = end.max(*max);
+                Some(*max)
+            })
+            .collect();
+
+        IntervalTree {
+            intervals,
+            max_ends,
+        }
+    }
+
+    /// Whether any interval overlaps the closed interval `[start, end]`
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        let count = self.intervals.partition_point(|&(s, _)| s <= end);
+        count > 0 && self.max_ends[count - 1] >= start
+    }
+
+    /// The last position covered by any interval
+    fn end(&self) -> u64 {
+        self.max_ends.last().cloned().unwrap_or(0)
+    }
+}
+
+/// Keep the records that overlap any of a set of regions, taking into account
+/// the reference allele or the END INFO field of each record.
+///
+/// Contigs are matched with or without a `chr` prefix, as BED files and VCFs
+/// often disagree on it. Unless told otherwise, the input is taken to be
+/// sorted, as indexed files are, and the stream stops once it passes the end
+/// of the last region of every contig.
+pub struct RegionFilter {
+    trees: HashMap<String, IntervalTree>,
+    sorted: bool,
+
+    // The contigs whose regions may still come up
+    pending: HashSet<String>,
+    contig: String,
+}
+
+impl RegionFilter {
+    pub fn new<I: IntoIterator<Item = Region>>(regions: I) -> RegionFilter {
+        let mut intervals: HashMap<String, Vec<(u64, u64)>> = HashMap::new();
+        for region in regions {
+            let name = contig_name(&region.name).to_string();
+            intervals
+                .entry(name)
+                .or_default()
+                .push((region.start, region.end));
+        }
+
+        let trees = intervals
+            .into_iter()
+            .map(|(name, intervals)| (name, IntervalTree::new(intervals)))
+            .collect::<HashMap<_, _>>();
+        let pending = trees.keys().cloned().collect();
+
+        RegionFilter {
+            trees,
+            sorted: true,
+            pending,
+            contig: String::new(),
+        }
+    }
+
+    /// Parse regions written as `chr:start-end`, in 1-based inclusive
+    /// coordinates, as in `index::Region`
+    pub fn parse<I, S>(regions: I) -> Result<RegionFilter, VcfError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let regions = regions
+            .into_iter()
+            .map(|region| {
+                let region = region.as_ref();
+                region
+                    .parse::<Region>()
+                    .map_err(|message| VcfError::syntax(None, None, region, message))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(RegionFilter::new(regions))
+    }
+
+    /// Read the regions of a BED file, whose intervals are 0-based and
+    /// exclude their end. Header, `track` and `browser` lines are skipped.
+    pub fn from_bed<R: BufRead>(reader: R) -> Result<RegionFilter, VcfError> {
+        let mut regions = Vec::new();
+
+        for (idx, line) in reader.lines().enumerate() {
+            let line_number = Some(idx + 1);
+            let line = line.map_err(|e| VcfError::io(line_number, e))?;
+
+            let ignored = ["#", "track", "browser"];
+            if line.trim().is_empty() || ignored.iter().any(|p| line.starts_with(p)) {
+                continue;
+            }
+
+            let fields = line.split('\t').collect::<Vec<_>>();
+            if fields.len() < 3 {
+                let message = format!("Line has {} columns but needs at least 3", fields.len());
+                return Err(VcfError::syntax(line_number, None, &line, message));
+            }
+
+            let position = |column: usize| {
+                fields[column].trim().parse::<u64>().map_err(|_| {
+                    let message = "Position is not a number";
+                    VcfError::syntax(line_number, Some(column + 1), fields[column], message)
+                })
+            };
+            let (start, end) = (position(1)?, position(2)?);
+            if start >= end {
+                let message = "Interval is empty";
+                return Err(VcfError::syntax(line_number, None, &line, message));
+            }
+
+            regions.push(Region::new(fields[0], start + 1, end));
+        }
+
+        Ok(RegionFilter::new(regions))
+    }
+
+    /// Do not stop at the end of the regions, for inputs that are not sorted
+    pub fn unsorted(mut self) -> RegionFilter {
+        self.sorted = false;
+        self
+    }
+}
+
+impl Filter for RegionFilter {
+    fn filter_item(&mut self, item: VCFData) -> DataAction {
+        let position = match item.position().parse::<u64>() {
+            Ok(position) => position,
+            Err(_) => return DataAction::Ignore,
+        };
+        let contig = contig_name(item.chromosome());
+
+        if self.sorted {
+            if contig != self.contig {
+                // Sorted inputs do not come back to a contig they left
+                self.pending.remove(&self.contig);
+                self.contig = contig.to_string();
+            }
+
+            let passed = match self.trees.get(contig) {
+                Some(tree) => position > tree.end(),
+                None => true,
+            };
+            if passed {
+                self.pending.remove(contig);
+                if self.pending.is_empty() {
+                    return DataAction::Stop;
+                }
+            }
+        }
+
+        let end = record_end(&item, position);
+        match self.trees.get(contig) {
+            Some(tree) if tree.overlaps(position, end) => DataAction::Data(item),
+            _ => DataAction::Ignore,
+        }
+    }
+}
+
+/// The name of a contig without the `chr` prefix
+fn contig_name(name: &str) -> &str {
+    name.strip_prefix("chr").unwrap_or(name)
+}
@@ -1,8 +1,23 @@
 use std::borrow::Borrow;
-use std::io::BufRead;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::mem;
+use std::path::Path;
+use std::str::FromStr;
+
+use flate2::bufread::MultiGzDecoder;
+use itertools::Either;
+use memchr::memchr_iter;
+
+use vcf::bgzf::{is_bgzf, is_gzip, BgzfReader};
+use vcf::error::VcfError;
+use vcf::genotype::{Genotype, GenotypeArray};
+use vcf::header::{Header, Value};
+use vcf::mmap::MappedVcf;
 
 /// The indices of each column in the VCF data lines
-enum VCFColumn {
+#[allow(clippy::upper_case_acronyms)]
+pub(crate) enum VCFColumn {
     CHROMOSOME = 0,
     POS = 1,
     ID = 2,
@@ -16,20 +31,124 @@ enum VCFColumn {
 
 static FIRST_DATA_COLUMN: usize = VCFColumn::FORMAT as usize + 1;
 
-/// A VCF data line
-pub struct VCFData {
-    line: String,
+/// The size of the buffer files are read through. Data lines of whole
+/// populations are hundreds of kilobytes long.
+const READ_BUFFER_SIZE: usize = 1 << 20;
+
+/// A VCF data line. The line is usually owned, but may be borrowed from a
+/// larger buffer, such as a memory-mapped file.
+#[derive(Default)]
+pub struct VCFData<L = String> {
+    line: L,
     ranges: Vec<(usize, usize)>,
+
+    // The genotype calls of the samples, when the source has them decoded
+    // already, as binary formats do, so that they need not be parsed again
+    calls: Option<GenotypeArray>,
 }
 
 impl VCFData {
-    fn new(line: String, ranges: Vec<(usize, usize)>) -> VCFData {
-        VCFData { line, ranges }
+    pub(crate) fn new(line: String, ranges: Vec<(usize, usize)>) -> VCFData {
+        VCFData {
+            line,
+            ranges,
+            calls: None,
+        }
+    }
+
+    /// Attach the calls of the samples, in the same order as their columns,
+    /// which must agree with the GT values in the line, or with DS where
+    /// there is no GT
+    pub(crate) fn with_calls(mut self, calls: GenotypeArray) -> VCFData {
+        self.calls = Some(calls);
+        self
+    }
+
+    /// Build a data line from its fields, which must include FORMAT, even if
+    /// empty
+    pub(crate) fn from_fields<I, S>(fields: I) -> VCFData
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut line = String::new();
+        let mut ranges = Vec::new();
+
+        for (idx, field) in fields.into_iter().enumerate() {
+            if idx > 0 {
+                line.push('\t');
+            }
+            let start = line.len();
+            line.push_str(field.as_ref());
+            ranges.push((start, line.len()));
+        }
+
+        VCFData::new(line, ranges)
+    }
+
+    /// Split a data line into its fields, checking that there are as many as
+    /// `columns`, the number of columns of the header line. When the file has
+    /// no genotype columns, an empty FORMAT field is inserted so that indices
+    /// line up.
+    pub(crate) fn parse(
+        line: String,
+        genotype: bool,
+        columns: usize,
+        line_number: Option<usize>,
+    ) -> Result<VCFData, VcfError> {
+        let mut data = VCFData::new(line, Vec::with_capacity(columns + 1));
+        data.tokenize(genotype, columns, line_number)?;
+        Ok(data)
     }
 
+    /// Find the fields of the line currently held, reusing the storage of the
+    /// previous ones
+    fn tokenize(
+        &mut self,
+        genotype: bool,
+        columns: usize,
+        line_number: Option<usize>,
+    ) -> Result<(), VcfError> {
+        let len = self.line.trim_end_matches(['\n', '\r']).len();
+        self.line.truncate(len);
+        self.calls = None;
+        tokenize(&self.line, &mut self.ranges, genotype, columns, line_number)
+    }
+}
+
+impl<'a> VCFData<&'a str> {
+    /// Split a data line borrowed from a larger buffer into its fields, like
+    /// `parse` does
+    pub(crate) fn parse_borrowed(
+        line: &'a str,
+        genotype: bool,
+        columns: usize,
+        line_number: Option<usize>,
+    ) -> Result<VCFData<&'a str>, VcfError> {
+        let line = line.trim_end_matches(['\n', '\r']);
+        let mut ranges = Vec::with_capacity(columns + 1);
+        tokenize(line, &mut ranges, genotype, columns, line_number)?;
+        Ok(VCFData {
+            line,
+            ranges,
+            calls: None,
+        })
+    }
+
+    /// Copy the line, so that the data no longer borrows from the buffer
+    pub fn into_owned(self) -> VCFData {
+        VCFData {
+            line: self.line.to_string(),
+            ranges: self.ranges,
+            calls: self.calls,
+        }
+    }
+}
+
+impl<L: AsRef<str>> VCFData<L> {
     fn field(&self, index: usize) -> &str {
         let (start, end) = self.ranges[index];
-        &self.line[start..end]
+        &self.line.as_ref()[start..end]
     }
 
     pub fn chromosome(&self) -> &str {
@@ -52,6 +171,14 @@ impl VCFData {
         self.field(VCFColumn::ALT as usize).split(',').collect()
     }
 
+    /// The number of alternate alleles, which is 0 when ALT is `.`
+    pub fn alternative_count(&self) -> usize {
+        match self.field(VCFColumn::ALT as usize) {
+            "." => 0,
+            alt => alt.split(',').count(),
+        }
+    }
+
     pub fn quality(&self) -> &str {
         self.field(VCFColumn::QUAL as usize)
     }
@@ -64,6 +191,45 @@ impl VCFData {
         self.field(VCFColumn::INFO as usize)
     }
 
+    /// The raw value of an INFO key: `None` if the key is absent and
+    /// `Some(None)` if it is present without a value, as flags are
+    pub fn info_raw(&self, key: &str) -> Option<Option<&str>> {
+        self.info().split(';').find_map(|field| {
+            let mut parts = field.splitn(2, '=');
+            if parts.next() == Some(key) {
+                Some(parts.next())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The value of an INFO key, converted according to its definition in the
+    /// header. Only this key is parsed, and only when asked for. Keys that the
+    /// header does not declare are returned as flags or strings. Errors have
+    /// the column but not the line, which `VcfError::at_line` can add.
+    pub fn info_field<'a>(
+        &'a self,
+        header: &Header,
+        key: &str,
+    ) -> Result<Option<Value<'a>>, VcfError> {
+        let raw = match self.info_raw(key) {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+
+        match header.info(key) {
+            Some(definition) => definition
+                .parse_value(raw, self.alternative_count(), 2)
+                .map_err(|message| {
+                    let column = Some(VCFColumn::INFO as usize + 1);
+                    let message = format!("INFO key {}: {}", key, message);
+                    VcfError::syntax(None, column, self.info(), message)
+                }),
+            None => Ok(Some(raw.map_or(Value::Flag, Value::String))),
+        }
+    }
+
     pub fn format(&self) -> &str {
         self.field(VCFColumn::FORMAT as usize)
     }
@@ -77,34 +243,178 @@ impl VCFData {
         range.map(move |idx| self.field(idx))
     }
 
-    pub(crate) fn keep<I, T>(self, indices: I) -> VCFData
+    /// The position of a tag in the FORMAT column
+    pub fn format_index(&self, key: &str) -> Option<usize> {
+        self.format().split(':').position(|k| k == key)
+    }
+
+    /// The raw value of a FORMAT tag for one sample, if present. Trailing
+    /// fields may be dropped from a sample, in which case this is `None`.
+    pub fn sample_raw(&self, index: usize, key: &str) -> Option<&str> {
+        let position = self.format_index(key)?;
+        self.genotype(index).split(':').nth(position)
+    }
+
+    /// The raw values of a FORMAT tag for all the samples
+    pub fn sample_values<'a>(&'a self, key: &str) -> impl Iterator<Item = Option<&'a str>> + 'a {
+        let position = self.format_index(key);
+        self.genotypes()
+            .map(move |sample| position.and_then(|p| sample.split(':').nth(p)))
+    }
+
+    /// The value of a FORMAT tag for one sample, converted according to its
+    /// definition in the header. Tags that the header does not declare are
+    /// returned as strings. Errors have the column but not the line, as for
+    /// `info_field`.
+    pub fn sample_field<'a>(
+        &'a self,
+        header: &Header,
+        index: usize,
+        key: &str,
+    ) -> Result<Option<Value<'a>>, VcfError> {
+        let raw = match self.sample_raw(index, key) {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+
+        let ploidy = self
+            .sample_raw(index, "GT")
+            .map_or(2, |gt| gt.split(['/', '|']).count());
+
+        match header.format(key) {
+            Some(definition) => definition
+                .parse_value(Some(raw), self.alternative_count(), ploidy)
+                .map_err(|message| {
+                    let column = Some(FIRST_DATA_COLUMN + index + 1);
+                    let message = format!("FORMAT key {}: {}", key, message);
+                    VcfError::syntax(None, column, self.genotype(index), message)
+                }),
+            None if raw == "." => Ok(None),
+            None => Ok(Some(Value::String(raw))),
+        }
+    }
+
+    /// The genotype call of a sample (`GT`), if present and well formed
+    pub fn call(&self, index: usize) -> Option<Genotype> {
+        match self.calls {
+            Some(ref calls) => calls.get(index),
+            None => self.sample_raw(index, "GT")?.parse().ok(),
+        }
+    }
+
+    /// The genotype calls of all the samples
+    pub fn calls<'a>(&'a self) -> impl Iterator<Item = Option<Genotype>> + 'a {
+        match self.calls {
+            Some(ref calls) => Either::Left((0..calls.len()).map(move |idx| calls.get(idx))),
+            None => Either::Right(
+                self.sample_values("GT")
+                    .map(|gt| gt.and_then(|gt| gt.parse().ok())),
+            ),
+        }
+    }
+
+    /// The calls of the samples as decoded by a binary reader, if any, which
+    /// are faster to inspect than parsing GT
+    pub fn decoded_calls(&self) -> Option<&GenotypeArray> {
+        self.calls.as_ref()
+    }
+
+    /// The dosage of the alternate alleles of a sample (`DS`)
+    pub fn dosage(&self, index: usize) -> Option<f32> {
+        self.sample_raw(index, "DS")?.parse().ok()
+    }
+
+    /// The probabilities of each possible genotype of a sample (`GP`)
+    pub fn genotype_probabilities(&self, index: usize) -> Option<Vec<f32>> {
+        parse_list(self.sample_raw(index, "GP")?)
+    }
+
+    /// The log10-scaled likelihoods of each possible genotype of a sample
+    /// (`GL`)
+    pub fn genotype_likelihoods(&self, index: usize) -> Option<Vec<f32>> {
+        parse_list(self.sample_raw(index, "GL")?)
+    }
+
+    /// The read depth of a sample (`DP`)
+    pub fn read_depth(&self, index: usize) -> Option<u32> {
+        self.sample_raw(index, "DP")?.parse().ok()
+    }
+
+    /// The read depth of each allele of a sample (`AD`)
+    pub fn allele_depths(&self, index: usize) -> Option<Vec<u32>> {
+        parse_list(self.sample_raw(index, "AD")?)
+    }
+
+    /// All the fields of the line, including only the kept genotype columns
+    pub(crate) fn fields(&self) -> impl Iterator<Item = &str> {
+        (0..self.ranges.len()).map(move |idx| self.field(idx))
+    }
+
+    pub(crate) fn keep<I, T>(self, indices: I) -> VCFData<L>
     where
         T: Borrow<usize>,
         I: Iterator<Item = T>,
     {
+        let indices = indices.map(|i| *i.borrow()).collect::<Vec<_>>();
+
         let mut ranges: Vec<_> = self.ranges[..FIRST_DATA_COLUMN].into();
-        ranges.extend(
-            indices
-                .into_iter()
-                .map(|i| self.ranges[i.borrow() + FIRST_DATA_COLUMN]),
-        );
+        ranges.extend(indices.iter().map(|i| self.ranges[i + FIRST_DATA_COLUMN]));
+        let calls = self.calls.map(|calls| calls.keep(indices));
 
         VCFData {
             line: self.line,
             ranges,
+            calls,
         }
     }
+}
 
-    pub(crate) fn debug(&self) {
-        for (idx, (start, end)) in self.ranges.iter().enumerate() {
-            let slice = self.line.get(*start..*end);
-            println!("{}: {}-{}\t{:?}", idx, start, end, slice);
-        }
+/// Find the fields of a line without its line break, checking that there are
+/// as many as `columns`. When the lines have no FORMAT column, an empty one is
+/// inserted so that the indices of the samples line up.
+fn tokenize(
+    line: &str,
+    ranges: &mut Vec<(usize, usize)>,
+    genotype: bool,
+    columns: usize,
+    line_number: Option<usize>,
+) -> Result<(), VcfError> {
+    // Tabs are ASCII, so they can be searched for in the bytes directly
+    let bytes = line.as_bytes();
+    let mut start = 0;
+    ranges.clear();
+    for idx in memchr_iter(b'\t', bytes) {
+        ranges.push((start, idx));
+        start = idx + 1;
     }
+    ranges.push((start, bytes.len()));
+
+    if ranges.len() != columns {
+        let message = format!(
+            "Line has {} columns but the header has {}",
+            ranges.len(),
+            columns
+        );
+        let column = Some(ranges.len().min(columns) + 1);
+        return Err(VcfError::syntax(line_number, column, line, message));
+    }
+
+    if !genotype {
+        let end = bytes.len();
+        ranges.insert(VCFColumn::FORMAT as usize, (end, end));
+    }
+
+    Ok(())
+}
+
+/// Parse a comma-separated list of numbers, failing if any is missing
+fn parse_list<T: FromStr>(raw: &str) -> Option<Vec<T>> {
+    raw.split(',').map(|v| v.parse().ok()).collect()
 }
 
 pub struct StreamUnfolded<S: DataStream> {
     pub metadata: Vec<(String, String)>,
+    pub header: Header,
     pub individuals: Vec<String>,
     pub stream: S,
 }
@@ -112,12 +422,9 @@ pub struct StreamUnfolded<S: DataStream> {
 pub trait MetadataReader {
     type Next: IndividualsReader;
 
-    fn read_metadata(
-        self,
-        metadata: &mut Vec<(String, String)>,
-    ) -> Result<Self::Next, &'static str>;
+    fn read_metadata(self, metadata: &mut Vec<(String, String)>) -> Result<Self::Next, VcfError>;
 
-    fn unfold(self) -> Result<StreamUnfolded<<Self::Next as IndividualsReader>::Next>, &'static str>
+    fn unfold(self) -> Result<StreamUnfolded<<Self::Next as IndividualsReader>::Next>, VcfError>
     where
         Self: Sized,
     {
@@ -126,9 +433,11 @@ pub trait MetadataReader {
 
         let stream = self.read_metadata(&mut metadata)?;
         let stream = stream.read_individuals(&mut individuals)?;
+        let header = Header::parse(&metadata);
 
         Ok(StreamUnfolded {
             metadata,
+            header,
             individuals,
             stream,
         })
@@ -138,21 +447,134 @@ pub trait MetadataReader {
 pub trait IndividualsReader {
     type Next: DataStream;
 
-    fn read_individuals(self, list: &mut Vec<String>) -> Result<Self::Next, &'static str>;
+    fn read_individuals(self, list: &mut Vec<String>) -> Result<Self::Next, VcfError>;
 }
 
-pub trait DataStream: Iterator<Item = VCFData> {}
+/// The data lines of a stream. Malformed lines and read errors are reported
+/// as they are found; iteration ends after an error the stream cannot recover
+/// from, such as a failed read.
+pub trait DataStream: Iterator<Item = Result<VCFData, VcfError>> {
+    /// Read the next record into `data`, reusing its storage where the stream
+    /// allows it, which avoids allocating for every record. Returns `None` at
+    /// the end of the stream.
+    fn read_into(&mut self, data: &mut VCFData) -> Option<Result<(), VcfError>> {
+        self.next().map(|result| result.map(|next| *data = next))
+    }
+}
 
 pub fn from_text_stream<R: BufRead>(text_stream: R) -> impl MetadataReader {
     MetadataReaderFromTextStream::new(text_stream)
 }
 
-struct MetadataReaderFromTextStream<R: BufRead> {
+/// Create a stream from a reader that may be plain text, gzip or BGZF. The
+/// format is detected from the magic bytes at the start of the reader.
+pub fn from_reader<R: BufRead + 'static>(reader: R) -> io::Result<impl MetadataReader> {
+    Ok(from_text_stream(decompress(reader)?))
+}
+
+/// Wrap a reader so that gzip and BGZF compressed contents are transparently
+/// decompressed
+pub(crate) fn decompress<R: BufRead + 'static>(reader: R) -> io::Result<Box<dyn BufRead>> {
+    // A BGZF header is 18 bytes long
+    let reader = peek(reader, 18)?;
+    let (bgzf, gzip) = (is_bgzf(reader.start()), is_gzip(reader.start()));
+
+    if bgzf {
+        Ok(Box::new(BgzfReader::new(reader)))
+    } else if gzip {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Read the first `len` bytes of a reader, or all of them if it is shorter,
+/// so that its format can be told from them. A single read may return fewer
+/// bytes, as from a pipe, so reading goes on until there are enough.
+pub fn peek<R: BufRead>(mut reader: R, len: usize) -> io::Result<Peeked<R>> {
+    let mut start = Vec::with_capacity(len);
+    while start.len() < len {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            break;
+        }
+        let count = buffer.len().min(len - start.len());
+        start.extend_from_slice(&buffer[..count]);
+        reader.consume(count);
+    }
+
+    Ok(Peeked {
+        start,
+        position: 0,
+        inner: reader,
+    })
+}
+
+/// A reader whose first bytes were read by `peek`, which gives them back
+/// before the rest
+pub struct Peeked<R> {
+    start: Vec<u8>,
+    position: usize,
+    inner: R,
+}
+
+impl<R> Peeked<R> {
+    /// The bytes read by `peek`
+    pub fn start(&self) -> &[u8] {
+        &self.start
+    }
+}
+
+impl<R: BufRead> Read for Peeked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = {
+            let available = self.fill_buf()?;
+            let count = available.len().min(buf.len());
+            buf[..count].copy_from_slice(&available[..count]);
+            count
+        };
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl<R: BufRead> BufRead for Peeked<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.position < self.start.len() {
+            Ok(&self.start[self.position..])
+        } else {
+            self.inner.fill_buf()
+        }
+    }
+
+    fn consume(&mut self, amount: usize) {
+        if self.position < self.start.len() {
+            self.position += amount;
+        } else {
+            self.inner.consume(amount);
+        }
+    }
+}
+
+/// Create a stream from the VCF file at the given path, which may be
+/// compressed
+pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<impl MetadataReader> {
+    let file = File::open(path)?;
+    from_reader(BufReader::with_capacity(READ_BUFFER_SIZE, file))
+}
+
+/// Map the uncompressed VCF file at the given path into memory, so that its
+/// records can be read without copying, starting at any line and in parallel
+pub fn from_mmap<P: AsRef<Path>>(path: P) -> Result<MappedVcf, VcfError> {
+    MappedVcf::open(path)
+}
+
+pub(crate) struct MetadataReaderFromTextStream<R: BufRead> {
     reader: R,
 }
 
 impl<R: BufRead> MetadataReaderFromTextStream<R> {
-    fn new(text_stream: R) -> MetadataReaderFromTextStream<R> {
+    pub(crate) fn new(text_stream: R) -> MetadataReaderFromTextStream<R> {
         MetadataReaderFromTextStream {
             reader: text_stream,
         }
@@ -162,10 +584,7 @@ impl<R: BufRead> MetadataReaderFromTextStream<R> {
 impl<R: BufRead> MetadataReader for MetadataReaderFromTextStream<R> {
     type Next = IndividualsReaderFromTextStream<R>;
 
-    fn read_metadata(
-        mut self,
-        map: &mut Vec<(String, String)>,
-    ) -> Result<Self::Next, &'static str> {
+    fn read_metadata(mut self, map: &mut Vec<(String, String)>) -> Result<Self::Next, VcfError> {
         let mut line = String::new();
         let mut count = 0;
 
@@ -173,40 +592,53 @@ impl<R: BufRead> MetadataReader for MetadataReaderFromTextStream<R> {
             line.clear();
 
             let line = match self.reader.read_line(&mut line) {
-                Err(_) => return Err("Error reading the text stream"),
-                Ok(len) if len == 0 && count == 0 => return Err("VCF file is empty"),
-                Ok(len) if len == 0 => return Err("VCF file contains only metadata"),
+                Err(e) => return Err(VcfError::io(Some(count + 1), e)),
+                Ok(0) if count == 0 => return Err("VCF file is empty".into()),
+                Ok(0) => return Err("VCF file contains only metadata".into()),
                 Ok(_) => &line,
             };
 
             count += 1;
 
-            let line = line.trim_right_matches("\n");
-            if line.starts_with("##") {
-                let fields = line[2..].splitn(2, "=").collect::<Vec<_>>();
-                map.push((fields[0].to_string(), fields[1].to_string()));
+            let line = line.trim_end_matches('\n');
+            if let Some(meta) = line.strip_prefix("##") {
+                let mut fields = meta.splitn(2, '=');
+                match (fields.next(), fields.next()) {
+                    (Some(key), Some(value)) => map.push((key.to_string(), value.to_string())),
+                    _ => {
+                        let message = "Metadata line is not of the form ##key=value";
+                        return Err(VcfError::syntax(Some(count), None, line, message));
+                    }
+                }
             } else {
                 return Ok(IndividualsReaderFromTextStream {
                     reader: self.reader,
                     line: line.to_string(),
+                    line_number: count,
                 });
             }
         }
     }
 }
 
-struct IndividualsReaderFromTextStream<R: BufRead> {
+pub(crate) struct IndividualsReaderFromTextStream<R: BufRead> {
     reader: R,
     line: String,
+    line_number: usize,
 }
 impl<R: BufRead> IndividualsReader for IndividualsReaderFromTextStream<R> {
     type Next = DataStreamFromTextStream<R>;
 
-    fn read_individuals(self, list: &mut Vec<String>) -> Result<Self::Next, &'static str> {
+    fn read_individuals(self, list: &mut Vec<String>) -> Result<Self::Next, VcfError> {
         let start_len = list.len();
 
-        let fields = self.line.split("\t").collect::<Vec<_>>();
-        let genotype = fields[VCFColumn::FORMAT as usize] == "FORMAT";
+        let fields = self.line.split('\t').collect::<Vec<_>>();
+        if !fields[0].starts_with("#CHROM") || fields.len() < VCFColumn::FORMAT as usize {
+            let message = "Header line does not start with the fixed columns";
+            return Err(VcfError::syntax(Some(self.line_number), None, &self.line, message));
+        }
+
+        let genotype = fields.get(VCFColumn::FORMAT as usize) == Some(&"FORMAT");
         let start_index = if genotype {
             VCFColumn::FORMAT as usize + 1
         } else {
@@ -215,77 +647,87 @@ impl<R: BufRead> IndividualsReader for IndividualsReaderFromTextStream<R> {
         list.extend(fields[start_index..].iter().map(|&s| s.to_string()));
 
         if list.len() == start_len {
-            Err("Line does not contain any individuals")
+            let message = "Line does not contain any individuals";
+            Err(VcfError::syntax(Some(self.line_number), None, &self.line, message))
         } else {
             Ok(DataStreamFromTextStream {
                 reader: self.reader,
                 genotype,
+                columns: fields.len(),
+                line_number: self.line_number,
+                line_capacity: 0,
+                failed: false,
             })
         }
     }
 }
 
-struct DataStreamFromTextStream<R: BufRead> {
+pub(crate) struct DataStreamFromTextStream<R: BufRead> {
     reader: R,
     genotype: bool,
+    columns: usize,
+    line_number: usize,
+    line_capacity: usize,
+    failed: bool,
+}
+
+impl<R: BufRead> DataStreamFromTextStream<R> {
+    /// Recover the underlying reader, positioned at the first data line,
+    /// whether the data lines contain genotype columns and how many columns
+    /// they have
+    pub(crate) fn into_inner(self) -> (R, bool, usize) {
+        (self.reader, self.genotype, self.columns)
+    }
 }
 
 impl<R: BufRead> Iterator for DataStreamFromTextStream<R> {
-    type Item = VCFData;
+    type Item = Result<VCFData, VcfError>;
+
+    fn next(&mut self) -> Option<Result<VCFData, VcfError>> {
+        // Size the buffers after the previous line, which saves growing them
+        // for each of the thousands of columns
+        let mut data = VCFData::new(
+            String::with_capacity(self.line_capacity),
+            Vec::with_capacity(self.columns + 1),
+        );
+        let result = self.read_into(&mut data)?;
+        Some(result.map(|_| data))
+    }
+}
 
-    fn next(&mut self) -> Option<VCFData> {
-        let mut line = String::new();
-        if self.reader.read_line(&mut line).is_err() || line.len() == 0 {
+impl<R: BufRead> DataStream for DataStreamFromTextStream<R> {
+    fn read_into(&mut self, data: &mut VCFData) -> Option<Result<(), VcfError>> {
+        if self.failed {
             return None;
         }
 
-        if line.ends_with('\n') {
-            line.pop();
-        }
-
-        let mut tabs = line
-            .chars()
-            .enumerate()
-            .filter(|(_, c)| *c == '\t')
-            .map(|(idx, _)| idx)
-            .collect::<Vec<_>>();
-
-        if !self.genotype {
-            let idx = VCFColumn::FORMAT as usize - 1;
-            let last_tab = tabs[idx];
-            tabs.insert(idx, last_tab);
-        }
-
-        let starts = vec![0]
-            .into_iter()
-            .chain(tabs.iter().cloned().map(|idx| idx + 1));
-        let ends = tabs.iter().cloned().chain(vec![line.len()].into_iter());
-        let ranges = starts.zip(ends).collect();
+        self.line_number += 1;
+
+        // Read bytes rather than a `String`, which checks the line is valid
+        // UTF-8 only once and in place
+        let mut buffer = mem::take(&mut data.line).into_bytes();
+        buffer.clear();
+
+        let result = match self.reader.read_until(b'\n', &mut buffer) {
+            Ok(0) => return None,
+            Ok(_) => match String::from_utf8(buffer) {
+                Ok(line) => {
+                    self.line_capacity = self.line_capacity.max(line.len());
+                    data.line = line;
+                    data.tokenize(self.genotype, self.columns, Some(self.line_number))
+                }
+                Err(e) => {
+                    let text = String::from_utf8_lossy(e.as_bytes()).into_owned();
+                    let message = "Line is not valid UTF-8";
+                    Err(VcfError::syntax(Some(self.line_number), None, &text, message))
+                }
+            },
+            Err(e) => Err(VcfError::io(Some(self.line_number), e)),
+        };
 
-        Some(VCFData::new(line, ranges))
+        // A malformed line does not affect the ones after it
+        self.failed = matches!(result, Err(VcfError::Io { .. }));
+        Some(result)
     }
 }
-impl<R: BufRead> DataStream for DataStreamFromTextStream<R> {}
-
-mod test {
-    use std::io;
-
-    use super::*;
 
-    fn main() -> Result<(), &'static str> {
-        let mut metadata = Vec::new();
-        let mut individuals = Vec::new();
-
-        let stdin = io::stdin();
-        let handle = stdin.lock();
-        let s = from_text_stream(handle);
-        let s = s.read_metadata(&mut metadata)?;
-        let s = s.read_individuals(&mut individuals)?;
-
-        for _polymorphism in s {
-            break;
-        }
-
-        Ok(())
-    }
-}
@@ -0,0 +1,414 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+
+use vcf::error::VcfError;
+use vcf::genotype::expected_ploidy;
+use vcf::header::Header;
+use vcf::population::Sex;
+use vcf::stream::{from_reader, MetadataReader, VCFColumn, VCFData};
+
+/// The kinds of problems found when validating a VCF
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Check {
+    /// The metadata lines or the `#CHROM` line are malformed
+    Header,
+    /// A data line does not have as many columns as the header
+    Columns,
+    /// POS is not a positive integer
+    Position,
+    /// Records of a contig are out of order or not contiguous
+    Order,
+    /// REF or ALT contain characters other than bases
+    Alphabet,
+    /// An INFO key is not declared or its value does not match the declaration
+    Info,
+    /// A FORMAT key is not declared or a value does not match the declaration
+    Format,
+    /// A GT refers to an allele that does not exist
+    AlleleIndex,
+    /// A sample has a different ploidy than in previous records of the contig
+    Ploidy,
+}
+
+impl Check {
+    /// The name of the check in reports
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Check::Header => "header",
+            Check::Columns => "columns",
+            Check::Position => "position",
+            Check::Order => "order",
+            Check::Alphabet => "alphabet",
+            Check::Info => "info",
+            Check::Format => "format",
+            Check::AlleleIndex => "allele_index",
+            Check::Ploidy => "ploidy",
+        }
+    }
+}
+
+/// A single problem found in a VCF. Lines and columns are 1-based.
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub check: Check,
+    pub message: String,
+}
+
+/// The result of validating a VCF
+#[derive(Debug, Default)]
+pub struct Report {
+    pub records: usize,
+    pub violations: Vec<Violation>,
+}
+
+impl Report {
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Write the violations as tab-separated values, one per line, after a
+    /// line naming the columns. Unknown lines and columns are written as `.`.
+    pub fn write_tsv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "line\tcolumn\tcheck\tmessage")?;
+
+        for violation in &self.violations {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                optional(violation.line),
+                optional(violation.column),
+                violation.check.name(),
+                violation.message
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn push<S: Into<String>>(&mut self, line: usize, column: usize, check: Check, message: S) {
+        self.violations.push(Violation {
+            line: Some(line),
+            column: Some(column),
+            check,
+            message: message.into(),
+        });
+    }
+}
+
+/// Validate a (possibly compressed) text VCF, collecting every violation
+/// instead of stopping at the first. Only failures to read the input are
+/// returned as errors.
+pub fn validate<R: BufRead + 'static>(reader: R) -> io::Result<Report> {
+    let mut report = Report::default();
+
+    let unfolded = match from_reader(reader)?.unfold() {
+        Ok(unfolded) => unfolded,
+        Err(VcfError::Io { error, .. }) => return Err(error),
+        Err(e) => {
+            report.violations.push(Violation {
+                line: e.line(),
+                column: None,
+                check: Check::Header,
+                message: e.to_string(),
+            });
+            return Ok(report);
+        }
+    };
+
+    // Malformed definitions do not stop the stream, but are violations
+    for &(idx, message) in &unfolded.header.malformed {
+        let (ref key, ref value) = unfolded.metadata[idx];
+        report.violations.push(Violation {
+            line: Some(idx + 1),
+            column: None,
+            check: Check::Header,
+            message: format!("{} (##{}={})", message, key, value),
+        });
+    }
+
+    let mut validator = Validator::new(&unfolded.header);
+
+    // The header line comes right after the metadata lines
+    let mut line = unfolded.metadata.len() + 1;
+
+    for data in unfolded.stream {
+        line += 1;
+        report.records += 1;
+
+        match data {
+            Ok(data) => validator.check(line, &data, &mut report),
+            Err(VcfError::Io { error, .. }) => return Err(error),
+            Err(e) => report.violations.push(Violation {
+                line: e.line(),
+                column: match e {
+                    VcfError::Syntax { column, .. } => column,
+                    _ => None,
+                },
+                check: Check::Columns,
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Keeps the state needed to check records against the ones before them
+struct Validator<'a> {
+    header: &'a Header,
+    contig: String,
+    position: u64,
+    finished: HashSet<String>,
+    ploidies: HashMap<(String, Option<usize>), Vec<usize>>,
+}
+
+impl<'a> Validator<'a> {
+    fn new(header: &'a Header) -> Validator<'a> {
+        Validator {
+            header,
+            contig: String::new(),
+            position: 0,
+            finished: HashSet::new(),
+            ploidies: HashMap::new(),
+        }
+    }
+
+    fn check(&mut self, line: usize, data: &VCFData, report: &mut Report) {
+        self.check_order(line, data, report);
+        self.check_alleles(line, data, report);
+        self.check_info(line, data, report);
+        self.check_samples(line, data, report);
+    }
+
+    fn check_order(&mut self, line: usize, data: &VCFData, report: &mut Report) {
+        let column = VCFColumn::POS as usize + 1;
+        let position = match data.position().parse::<u64>() {
+            Ok(position) if position > 0 => position,
+            _ => {
+                let message = format!("POS {:?} is not a positive integer", data.position());
+                report.push(line, column, Check::Position, message);
+                return;
+            }
+        };
+
+        let contig = data.chromosome();
+        if contig != self.contig {
+            if self.finished.contains(contig) {
+                let message = format!("Records of contig {} are not contiguous", contig);
+                report.push(line, column - 1, Check::Order, message);
+            }
+            let previous = ::std::mem::replace(&mut self.contig, contig.to_string());
+            self.finished.insert(previous);
+        } else if position < self.position {
+            let message = format!("POS {} comes after {}", position, self.position);
+            report.push(line, column, Check::Order, message);
+        }
+
+        self.position = position;
+    }
+
+    fn check_alleles(&self, line: usize, data: &VCFData, report: &mut Report) {
+        let reference = data.reference();
+        if reference.is_empty() || !reference.chars().all(is_base) {
+            let message = format!("REF {:?} is not a sequence of bases", reference);
+            report.push(line, VCFColumn::REF as usize + 1, Check::Alphabet, message);
+        }
+
+        for alternative in data.alternatives() {
+            if !is_alternative(alternative) {
+                let message = format!("ALT {:?} is not a valid allele", alternative);
+                report.push(line, VCFColumn::ALT as usize + 1, Check::Alphabet, message);
+            }
+        }
+    }
+
+    fn check_info(&self, line: usize, data: &VCFData, report: &mut Report) {
+        let info = data.info();
+        if info == "." {
+            return;
+        }
+
+        let column = VCFColumn::INFO as usize + 1;
+
+        for field in info.split(';') {
+            let key = field.split('=').next().unwrap_or_default();
+            if self.header.info(key).is_none() {
+                let message = format!("INFO key {} is not declared in the header", key);
+                report.push(line, column, Check::Info, message);
+            } else if let Err(error) = data.info_field(self.header, key) {
+                report.push(line, column, Check::Info, message(error));
+            }
+        }
+    }
+
+    fn check_samples(&mut self, line: usize, data: &VCFData, report: &mut Report) {
+        let format = data.format();
+        if format.is_empty() {
+            return;
+        }
+
+        let mut declared = Vec::new();
+        for key in format.split(':') {
+            if self.header.format(key).is_some() {
+                declared.push(key);
+            } else {
+                let message = format!("FORMAT key {} is not declared in the header", key);
+                report.push(line, VCFColumn::FORMAT as usize + 1, Check::Format, message);
+            }
+        }
+
+        let alternatives = data.alternative_count();
+        let gt_index = format.split(':').position(|key| key == "GT");
+
+        // Males carry one copy of chrX outside the pseudoautosomal regions but
+        // two inside them, so the ploidies of both are kept apart
+        let position = data.position().parse().unwrap_or(0);
+        let region = expected_ploidy(data.chromosome(), position, Sex::Male);
+        let ploidies = self
+            .ploidies
+            .entry((data.chromosome().to_string(), region))
+            .or_default();
+
+        for (idx, sample) in data.genotypes().enumerate() {
+            let column = VCFColumn::FORMAT as usize + 2 + idx;
+            let values = sample.split(':').collect::<Vec<_>>();
+            if values.len() > format.split(':').count() {
+                let message = "Sample has more values than FORMAT keys";
+                report.push(line, column, Check::Format, message);
+            }
+
+            let gt = gt_index.and_then(|gt_index| values.get(gt_index));
+            let ploidy = gt.map_or(2, |gt| gt.split(['/', '|']).count());
+
+            if let Some(gt) = gt {
+                for allele in gt.split(['/', '|']).filter(|&allele| allele != ".") {
+                    match allele.parse::<usize>() {
+                        Ok(index) if index <= alternatives => {}
+                        _ => {
+                            let message = format!(
+                                "GT {:?} refers to an allele other than the {} in REF and ALT",
+                                gt,
+                                alternatives + 1
+                            );
+                            report.push(line, column, Check::AlleleIndex, message);
+                            break;
+                        }
+                    }
+                }
+
+                // A lone `.` says nothing about the ploidy of the sample
+                if *gt != "." {
+                    if ploidies.len() <= idx {
+                        ploidies.resize(idx + 1, 0);
+                    }
+                    if ploidies[idx] == 0 {
+                        ploidies[idx] = ploidy;
+                    } else if ploidies[idx] != ploidy {
+                        let message = format!(
+                            "Sample has ploidy {} but {} in earlier records of contig {}",
+                            ploidy,
+                            ploidies[idx],
+                            data.chromosome()
+                        );
+                        report.push(line, column, Check::Ploidy, message);
+                    }
+                }
+            }
+
+            for key in &declared {
+                if let Err(error) = data.sample_field(self.header, idx, key) {
+                    report.push(line, column, Check::Format, message(error));
+                }
+            }
+        }
+    }
+}
+
+/// The message of an error from a typed field access, without its position
+fn message(error: VcfError) -> String {
+    match error {
+        VcfError::Syntax { message, .. } => message,
+        error => error.to_string(),
+    }
+}
+
+fn is_base(c: char) -> bool {
+    matches!(c, 'A' | 'C' | 'G' | 'T' | 'N' | 'a' | 'c' | 'g' | 't' | 'n')
+}
+
+/// Whether an ALT allele is a sequence of bases, a symbolic allele such as
+/// `<DEL>`, a breakend, the `*` of an overlapping deletion or missing
+fn is_alternative(allele: &str) -> bool {
+    if allele == "*" || allele == "." {
+        return true;
+    }
+    if allele.starts_with('<') && allele.ends_with('>') && allele.len() > 2 {
+        return true;
+    }
+    if allele.contains('[') || allele.contains(']') {
+        return allele
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "[]:._".contains(c));
+    }
+    if allele.starts_with('.') || allele.ends_with('.') {
+        // Single breakends
+        return allele.trim_matches('.').chars().all(is_base);
+    }
+
+    !allele.is_empty() && allele.chars().all(is_base)
+}
+
+fn optional(value: Option<usize>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => ".".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "##fileformat=VCFv4.2
+##contig=<ID=X>
+##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tmale\tfemale
+";
+
+    fn check(records: &[&str]) -> Vec<(Option<usize>, Check)> {
+        let text = format!("{}{}\n", HEADER, records.join("\n"));
+        let report = validate(io::Cursor::new(text.into_bytes())).unwrap();
+        report
+            .violations
+            .iter()
+            .map(|violation| (violation.line, violation.check))
+            .collect()
+    }
+
+    #[test]
+    fn males_are_diploid_in_the_pseudoautosomal_regions_of_x() {
+        let records = [
+            "X\t60000\t.\tA\tG\t.\tPASS\t.\tGT\t1\t0/1",
+            "X\t60001\t.\tA\tG\t.\tPASS\t.\tGT\t0/1\t0/1",
+            "X\t2699520\t.\tA\tG\t.\tPASS\t.\tGT\t1/1\t0/0",
+            "X\t2699521\t.\tA\tG\t.\tPASS\t.\tGT\t0\t0/1",
+        ];
+        assert_eq!(check(&records), vec![]);
+    }
+
+    #[test]
+    fn ploidy_changes_within_a_region_are_violations() {
+        let records = [
+            "X\t60001\t.\tA\tG\t.\tPASS\t.\tGT\t0/1\t0/1",
+            "X\t60002\t.\tA\tG\t.\tPASS\t.\tGT\t1\t0/1",
+            "X\t2699521\t.\tA\tG\t.\tPASS\t.\tGT\t0\t0/1",
+            "X\t2699522\t.\tA\tG\t.\tPASS\t.\tGT\t0/1\t0/1",
+        ];
+        assert_eq!(
+            check(&records),
+            vec![(Some(6), Check::Ploidy), (Some(8), Check::Ploidy)]
+        );
+    }
+}
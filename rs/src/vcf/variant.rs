@@ -0,0 +1,127 @@
+use std::str::FromStr;
+
+use vcf::filter::{DataAction, Filter};
+use vcf::stream::VCFData;
+
+/// The kind of change an alternate allele makes to the reference
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VariantType {
+    /// A single base replaced by another
+    Snp,
+    /// Several consecutive bases replaced by as many others
+    Mnp,
+    /// Bases inserted or deleted
+    Indel,
+    /// A symbolic allele, such as `<DEL>` or `<CN2>`, or a breakend
+    Structural,
+}
+
+impl VariantType {
+    /// The type of an alternate allele, or `None` for the `*` of an
+    /// overlapping deletion, a missing allele and one equal to the reference
+    pub fn classify(reference: &str, alternative: &str) -> Option<VariantType> {
+        if alternative == "*" || alternative == "." {
+            return None;
+        }
+        if alternative.starts_with('<')
+            || alternative.contains('[')
+            || alternative.contains(']')
+            || alternative.starts_with('.')
+            || alternative.ends_with('.')
+        {
+            return Some(VariantType::Structural);
+        }
+
+        if reference.len() != alternative.len() {
+            Some(VariantType::Indel)
+        } else if reference.eq_ignore_ascii_case(alternative) {
+            None
+        } else if reference.len() == 1 {
+            Some(VariantType::Snp)
+        } else {
+            Some(VariantType::Mnp)
+        }
+    }
+
+    /// The type of each alternate allele of a record. The `VT` INFO tag of the
+    /// 1000 Genomes data takes precedence if it has one known value, or one
+    /// per alternate allele; otherwise the alleles themselves are compared.
+    pub fn of<L: AsRef<str>>(data: &VCFData<L>) -> Vec<Option<VariantType>> {
+        let alternatives = match data.alternative_count() {
+            0 => Vec::new(),
+            _ => data.alternatives(),
+        };
+
+        let tagged = data.info_raw("VT").and_then(|vt| vt).and_then(|vt| {
+            vt.split(',')
+                .map(|v| v.parse().ok())
+                .collect::<Option<Vec<_>>>()
+        });
+
+        match tagged {
+            Some(ref types) if types.len() == 1 => vec![Some(types[0]); alternatives.len()],
+            Some(types) if types.len() == alternatives.len() => {
+                types.into_iter().map(Some).collect()
+            }
+            _ => alternatives
+                .iter()
+                .map(|alternative| VariantType::classify(data.reference(), alternative))
+                .collect(),
+        }
+    }
+}
+
+impl FromStr for VariantType {
+    type Err = &'static str;
+
+    /// Parse the names used in `VT`, in any case
+    fn from_str(s: &str) -> Result<VariantType, &'static str> {
+        match s.to_ascii_uppercase().as_str() {
+            "SNP" => Ok(VariantType::Snp),
+            "MNP" => Ok(VariantType::Mnp),
+            "INDEL" => Ok(VariantType::Indel),
+            "SV" => Ok(VariantType::Structural),
+            _ => Err("Variant type is not one of SNP, MNP, INDEL or SV"),
+        }
+    }
+}
+
+/// Keep the records whose alternate alleles are all of the given types,
+/// ignoring `*` and missing alleles. Records with no such allele, as those
+/// with ALT `.`, are dropped.
+pub struct VariantTypeFilter {
+    types: Vec<VariantType>,
+    biallelic: bool,
+}
+
+impl VariantTypeFilter {
+    pub fn new<I: IntoIterator<Item = VariantType>>(types: I) -> VariantTypeFilter {
+        VariantTypeFilter {
+            types: types.into_iter().collect(),
+            biallelic: false,
+        }
+    }
+
+    /// Also drop the records with more than one alternate allele
+    pub fn biallelic(mut self) -> VariantTypeFilter {
+        self.biallelic = true;
+        self
+    }
+}
+
+impl Filter for VariantTypeFilter {
+    fn filter_item(&mut self, item: VCFData) -> DataAction {
+        if self.biallelic && item.alternative_count() != 1 {
+            return DataAction::Ignore;
+        }
+
+        let types = VariantType::of(&item);
+        let mut types = types.iter().flatten().peekable();
+
+        if types.peek().is_some() && types.all(|t| self.types.contains(t)) {
+            DataAction::Data(item)
+        } else {
+            DataAction::Ignore
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use std::io::{self, Write};
+
+use vcf::stream::{DataStream, MetadataReader, VCFData};
+
+/// The fixed columns of the `#CHROM` header line
+const HEADER_COLUMNS: [&str; 8] = [
+    "#CHROM", "POS", "ID", "REF", "ALT", "QUAL", "FILTER", "INFO",
+];
+
+/// Serializes a stream back into VCF text
+pub struct Writer<W: Write> {
+    writer: W,
+    genotype: bool,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(writer: W) -> Writer<W> {
+        Writer {
+            writer,
+            genotype: true,
+        }
+    }
+
+    /// Write the `##` metadata lines and the `#CHROM` header line. The FORMAT
+    /// column, and with it the genotype columns of the data lines, is only
+    /// written when there are individuals.
+    pub fn write_header(
+        &mut self,
+        metadata: &[(String, String)],
+        individuals: &[String],
+    ) -> io::Result<()> {
+        for (key, value) in metadata {
+            writeln!(self.writer, "##{}={}", key, value)?;
+        }
+
+        self.writer
+            .write_all(HEADER_COLUMNS.join("\t").as_bytes())?;
+
+        self.genotype = !individuals.is_empty();
+        if self.genotype {
+            self.writer.write_all(b"\tFORMAT")?;
+            for individual in individuals {
+                write!(self.writer, "\t{}", individual)?;
+            }
+        }
+
+        self.writer.write_all(b"\n")
+    }
+
+    /// Write a data line, with only the genotype columns it has kept
+    pub fn write_data(&mut self, data: &VCFData) -> io::Result<()> {
+        let columns = if self.genotype {
+            usize::MAX
+        } else {
+            HEADER_COLUMNS.len()
+        };
+
+        for (idx, field) in data.fields().take(columns).enumerate() {
+            if idx > 0 {
+                self.writer.write_all(b"\t")?;
+            }
+            self.writer.write_all(field.as_bytes())?;
+        }
+
+        self.writer.write_all(b"\n")
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Consume a stream and write all of it, header included
+pub fn write_stream<S, W>(stream: S, writer: W) -> io::Result<()>
+where
+    S: MetadataReader,
+    W: Write,
+{
+    let unfolded = stream.unfold()?;
+
+    let mut writer = Writer::new(writer);
+    writer.write_header(&unfolded.metadata, &unfolded.individuals)?;
+
+    let mut stream = unfolded.stream;
+    let mut data = VCFData::default();
+    while let Some(result) = stream.read_into(&mut data) {
+        result?;
+        writer.write_data(&data)?;
+    }
+
+    writer.flush()
+}